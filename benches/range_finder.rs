@@ -0,0 +1,34 @@
+use adventure_encounters::helpers::{
+    creature::MovementModes,
+    map::Map,
+    map_builder::{rooms::RoomsArchitect, themes::MapTheme, MapBuilder},
+    range_finder::RangeFinder,
+};
+use bracket_geometry::prelude::Point;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+fn open_field(c: &mut Criterion) {
+    let map = Map::new(80, 50, MapTheme::named("ForestTheme").unwrap());
+    let anchor = Point::new(40, 25);
+    let mut group = c.benchmark_group("compute_grid/open_field");
+    for range in [30, 60, 120] {
+        group.bench_with_input(BenchmarkId::from_parameter(range), &range, |b, range| {
+            b.iter(|| RangeFinder::compute_grid(black_box(anchor), *range, &map, MovementModes::default()))
+        });
+    }
+    group.finish();
+}
+
+fn rooms(c: &mut Criterion) {
+    let mb = MapBuilder::new(Box::new(RoomsArchitect {}), MapTheme::named("DungeonTheme").unwrap(), 80, 50, 1);
+    let mut group = c.benchmark_group("compute_grid/rooms");
+    for range in [30, 60, 120] {
+        group.bench_with_input(BenchmarkId::from_parameter(range), &range, |b, range| {
+            b.iter(|| RangeFinder::compute_grid(black_box(mb.player_start), *range, &mb.map, MovementModes::default()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, open_field, rooms);
+criterion_main!(benches);
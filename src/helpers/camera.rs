@@ -1,4 +1,13 @@
-use bevy::{input::Input, math::Vec3, prelude::*, render::camera::Camera};
+use bevy::{input::Input, math::Vec3, prelude::*, render::camera::Camera, window::PrimaryWindow};
+use bevy_ecs_tilemap::prelude::*;
+use bracket_geometry::prelude::Point;
+
+use super::map::ObjectsMapLayer;
+use super::map_builder::MapBuilder;
+
+// The map point under the mouse cursor, None when the cursor is outside the map or window
+#[derive(Resource, Default)]
+pub struct CursorMapPoint(pub Option<Point>);
 
 // A simple camera system for moving and zooming the camera.
 #[allow(dead_code)]
@@ -44,4 +53,31 @@ pub fn movement(
         // Bevy has a specific camera setup and this can mess with how our layers are shown.
         transform.translation.z = z;
     }
-}
\ No newline at end of file
+}
+// Tracks which map tile the mouse cursor is hovering over
+pub fn cursor_map_point_system(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    layers: Query<(&TilemapSize, &TilemapGridSize, &TilemapType, &Transform), With<ObjectsMapLayer>>,
+    mb: Res<MapBuilder>,
+    mut cursor_map_point: ResMut<CursorMapPoint>,
+) {
+    let mut point = None;
+    if let (Ok(window), Ok((camera, camera_transform)), Ok((map_size, grid_size, map_type, map_transform))) =
+        (windows.get_single(), cameras.get_single(), layers.get_single())
+    {
+        if let Some(world_position) = window
+            .cursor_position()
+            .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor))
+        {
+            // Tile positions are relative to the tilemap's own transform
+            let map_position = world_position - map_transform.translation.truncate();
+            if let Some(tile_pos) = TilePos::from_world_pos(&map_position, map_size, grid_size, map_type) {
+                point = Some(mb.map.from_bevy_ecs_tilemap(&tile_pos));
+            }
+        }
+    }
+    if cursor_map_point.0 != point {
+        cursor_map_point.0 = point;
+    }
+}
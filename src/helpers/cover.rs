@@ -0,0 +1,150 @@
+use serde::Deserialize;
+
+use crate::prelude::*;
+
+use super::map::Map;
+
+// How much of a creature an obstacle hides, ordered from least to most
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum Cover {
+    #[default]
+    None,
+    Half,
+    ThreeQuarters,
+    Full,
+}
+
+impl Cover {
+    // Bonus to armor class. A target behind full cover can't be targeted at all.
+    pub fn armor_class_bonus(&self) -> i32 {
+        match self {
+            Cover::None | Cover::Full => 0,
+            Cover::Half => 2,
+            Cover::ThreeQuarters => 5,
+        }
+    }
+}
+
+// Samples taken per tile of line length when tracing for obstacles
+const SAMPLES_PER_TILE: i32 = 16;
+
+fn corners(point: Point) -> [(f32, f32); 4] {
+    let (x, y) = (point.x as f32, point.y as f32);
+    [(x, y), (x + 1.0, y), (x, y + 1.0), (x + 1.0, y + 1.0)]
+}
+
+// The most cover from any tile a line passes through, where anything that
+// stops projectiles is full cover. A line running along
+// the edge between two tiles is only blocked as much as the more open of the
+// two, and touching a tile's corner doesn't count as passing through it.
+fn line_cover(map: &Map, from: (f32, f32), to: (f32, f32), ends: [Point; 2]) -> Cover {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let samples = (dx.abs().max(dy.abs()).ceil() as i32).max(1) * SAMPLES_PER_TILE;
+    let tile_cover = |point: Point| {
+        if ends.contains(&point) || !map.in_bounds(point) {
+            Cover::None
+        } else {
+            let tile = &map.tiles[map.map_idx(point.x, point.y)];
+            if tile.blocks_projectiles { Cover::Full } else { tile.cover }
+        }
+    };
+    let mut cover = Cover::None;
+    for i in 0..samples {
+        let t = (i as f32 + 0.5) / samples as f32;
+        let (x, y) = (from.0 + dx * t, from.1 + dy * t);
+        let on_x_edge = (x - x.round()).abs() < 1e-4;
+        let on_y_edge = (y - y.round()).abs() < 1e-4;
+        let sample_cover = match (on_x_edge, on_y_edge) {
+            (true, true) => continue,
+            (true, false) => {
+                let (x, y) = (x.round() as i32, y.floor() as i32);
+                tile_cover(Point::new(x - 1, y)).min(tile_cover(Point::new(x, y)))
+            }
+            (false, true) => {
+                let (x, y) = (x.floor() as i32, y.round() as i32);
+                tile_cover(Point::new(x, y - 1)).min(tile_cover(Point::new(x, y)))
+            }
+            (false, false) => tile_cover(Point::new(x.floor() as i32, y.floor() as i32)),
+        };
+        cover = cover.max(sample_cover);
+    }
+    cover
+}
+
+// The cover a target has against an attacker. The attacker picks the corner
+// of their tile with the clearest view and traces a line to each corner of
+// the target's tile: one or two blocked lines give half cover, three or four
+// give three-quarters, and four lines through full cover hide the target
+// completely. No line gives more cover than the obstacles it passes through.
+pub fn cover_between(map: &Map, attacker: Point, target: Point) -> Cover {
+    corners(attacker)
+        .into_iter()
+        .map(|from| {
+            let lines = corners(target).map(|to| line_cover(map, from, to, [attacker, target]));
+            let blocked = lines.iter().filter(|cover| **cover != Cover::None).count();
+            let worst = lines.iter().copied().max().unwrap_or_default();
+            match blocked {
+                0 => Cover::None,
+                1 | 2 => Cover::Half.min(worst),
+                _ if lines.iter().all(|cover| *cover == Cover::Full) => Cover::Full,
+                _ => Cover::ThreeQuarters.min(worst),
+            }
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::map_builder::themes::MapTheme;
+    use crate::helpers::tiles::Tile;
+
+    fn map_with(tiles: &[(i32, i32, &str)]) -> Map {
+        let mut map = Map::new(10, 10, MapTheme::named("DungeonTheme").unwrap());
+        for (x, y, name) in tiles {
+            let idx = map.map_idx(*x, *y);
+            map.tiles[idx] = Tile::named(name).unwrap();
+        }
+        map
+    }
+
+    #[test]
+    fn open_ground_gives_no_cover() {
+        let map = map_with(&[]);
+        assert_eq!(cover_between(&map, Point::new(1, 1), Point::new(8, 6)), Cover::None);
+        assert_eq!(cover_between(&map, Point::new(1, 1), Point::new(2, 2)), Cover::None);
+    }
+
+    #[test]
+    fn obstacles_give_their_own_cover_at_most() {
+        let attacker = Point::new(1, 5);
+        let target = Point::new(6, 5);
+        let low_wall = map_with(&[(5, 4, "wall_stone_low"), (5, 5, "wall_stone_low"), (5, 6, "wall_stone_low")]);
+        assert_eq!(cover_between(&low_wall, attacker, target), Cover::Half);
+        let barrel = map_with(&[(5, 4, "barrel"), (5, 5, "barrel"), (5, 6, "barrel")]);
+        assert_eq!(cover_between(&barrel, attacker, target), Cover::ThreeQuarters);
+        let wall = map_with(&[(5, 4, "wall_shoals_2"), (5, 5, "wall_shoals_2"), (5, 6, "wall_shoals_2")]);
+        assert_eq!(cover_between(&wall, attacker, target), Cover::Full);
+    }
+
+    #[test]
+    fn windows_can_be_seen_through_but_not_shot_through() {
+        let attacker = Point::new(1, 5);
+        let target = Point::new(6, 5);
+        let map = map_with(&[(5, 4, "window"), (5, 5, "window"), (5, 6, "window")]);
+        assert_eq!(cover_between(&map, attacker, target), Cover::Full);
+        assert!(field_of_view_set(attacker, 8, &map).contains(&target));
+        let map = map_with(&[(5, 4, "curtain"), (5, 5, "curtain"), (5, 6, "curtain")]);
+        assert_eq!(cover_between(&map, attacker, target), Cover::None);
+        assert!(!field_of_view_set(attacker, 8, &map).contains(&target));
+    }
+
+    #[test]
+    fn peeking_round_a_corner_gives_partial_cover() {
+        // A wall ending level with the target only blocks some of the lines
+        let map = map_with(&[(5, 5, "wall_shoals_2"), (5, 6, "wall_shoals_2"), (5, 7, "wall_shoals_2")]);
+        let cover = cover_between(&map, Point::new(1, 4), Point::new(7, 5));
+        assert!(cover > Cover::None && cover < Cover::Full, "{:?}", cover);
+    }
+}
@@ -0,0 +1,267 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+
+use ron::de::from_reader;
+use ron::error::SpannedError;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{*, field_of_view::FieldOfView, movement::Movement};
+
+const CREATURES_PATH: &str = "resources/creatures";
+
+// Why a creature file couldn't be loaded
+#[derive(Debug)]
+pub enum CreatureError {
+    Io { name: String, source: io::Error },
+    Parse { name: String, source: SpannedError },
+}
+
+impl fmt::Display for CreatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CreatureError::Io { name, source } => write!(f, "Couldn't read {}/{}.ron: {}", CREATURES_PATH, name, source),
+            CreatureError::Parse { name, source } => write!(f, "Couldn't parse {}/{}.ron: {}", CREATURES_PATH, name, source),
+        }
+    }
+}
+
+impl Error for CreatureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CreatureError::Io { source, .. } => Some(source),
+            CreatureError::Parse { source, .. } => Some(source),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+pub enum Size {
+    Tiny,
+    Small,
+    Medium,
+    Large,
+    Huge,
+    Gargantuan,
+}
+
+impl Size {
+    // Tiles per side of the space the creature controls
+    pub fn space_in_tiles(&self) -> u8 {
+        match self {
+            Size::Tiny | Size::Small | Size::Medium => 1,
+            Size::Large => 2,
+            Size::Huge => 3,
+            Size::Gargantuan => 4,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct AbilityScores {
+    pub strength: u8,
+    pub dexterity: u8,
+    pub constitution: u8,
+    pub intelligence: u8,
+    pub wisdom: u8,
+    pub charisma: u8,
+}
+
+impl AbilityScores {
+    pub fn modifier(score: u8) -> i32 {
+        (score as i32 - 10).div_euclid(2)
+    }
+}
+
+// Feet per turn for each way of moving, None when the creature can't move that way
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Speed {
+    pub walk: u32,
+    #[serde(default)]
+    pub swim: Option<u32>,
+    #[serde(default)]
+    pub climb: Option<u32>,
+    #[serde(default)]
+    pub fly: Option<u32>,
+    #[serde(default)]
+    pub burrow: Option<u32>,
+}
+
+impl Speed {
+    pub fn modes(&self) -> MovementModes {
+        MovementModes {
+            swim: self.swim.is_some(),
+            climb: self.climb.is_some(),
+            fly: self.fly.is_some(),
+            burrow: self.burrow.is_some(),
+        }
+    }
+}
+
+// The ways a creature can move besides walking. Every creature spends its
+// walking speed, the other modes only change what each tile costs.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Hash)]
+pub struct MovementModes {
+    pub swim: bool,
+    pub climb: bool,
+    pub fly: bool,
+    pub burrow: bool,
+}
+
+// Ranges in feet
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Senses {
+    pub sight: u16,
+    #[serde(default)]
+    pub darkvision: Option<u16>,
+    #[serde(default)]
+    pub blindsight: Option<u16>,
+}
+
+// Ranges in feet
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum AttackRange {
+    Melee { reach: u16 },
+    Ranged { normal: u16, long: u16 },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct AttackProfile {
+    pub name: String,
+    pub to_hit: i32,
+    #[serde(with = "dice_expression")]
+    pub damage: DiceType, // written as a dice expression such as "2d6+3"
+    pub range: AttackRange,
+}
+
+// Reads a dice expression such as "2d6+3" or "1d4". Unlike parse_dice_string,
+// which skips whatever it doesn't understand, anything left over is refused.
+pub fn parse_dice(expression: &str) -> Option<DiceType> {
+    let expression: String = expression.split_whitespace().collect();
+    let (n_dice, rest) = expression.split_once('d')?;
+    let (die_type, bonus) = match rest.find(['+', '-']) {
+        Some(sign) => (&rest[..sign], rest[sign..].parse().ok()?),
+        None => (rest, 0),
+    };
+    let digits = |text: &str| !text.is_empty() && text.chars().all(|c| c.is_ascii_digit());
+    if !digits(n_dice) || !digits(die_type) {
+        return None;
+    }
+    let dice = DiceType::new(n_dice.parse().ok()?, die_type.parse().ok()?, bonus);
+    (dice.n_dice > 0 && dice.die_type > 0).then_some(dice)
+}
+
+// Creature files write damage as a dice expression rather than its parts
+mod dice_expression {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::{parse_dice, DiceType};
+
+    pub fn serialize<S: Serializer>(dice: &DiceType, serializer: S) -> Result<S::Ok, S::Error> {
+        let bonus = match dice.bonus {
+            0 => String::new(),
+            bonus => format!("{:+}", bonus),
+        };
+        serializer.serialize_str(&format!("{}d{}{}", dice.n_dice, dice.die_type, bonus))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DiceType, D::Error> {
+        let expression = String::deserialize(deserializer)?;
+        parse_dice(&expression).ok_or_else(|| de::Error::custom(format!("{} isn't a dice expression such as 2d6+3", expression)))
+    }
+}
+
+#[derive(Component, Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct StatBlock {
+    pub name: String,
+    pub sprite_index: usize, // in monsters.png
+    pub size: Size,
+    pub armor_class: i32,
+    pub hit_points: i32,
+    pub speed: Speed,
+    pub abilities: AbilityScores,
+    pub senses: Senses,
+    #[serde(default)]
+    pub attacks: Vec<AttackProfile>,
+}
+
+impl StatBlock {
+    // Reads resources/creatures/NAME.ron
+    pub fn load(name: &str) -> Result<Self, CreatureError> {
+        let path = format!("{}/{}.ron", CREATURES_PATH, name);
+        let file = File::open(path).map_err(|source| CreatureError::Io { name: name.to_string(), source })?;
+        from_reader(file).map_err(|source| CreatureError::Parse { name: name.to_string(), source })
+    }
+    pub fn initiative_modifier(&self) -> i32 {
+        AbilityScores::modifier(self.abilities.dexterity)
+    }
+    pub fn field_of_view(&self) -> FieldOfView {
+        FieldOfView::new(self.senses.sight, Some(0), self.senses.darkvision)
+    }
+    pub fn movement(&self) -> Movement {
+        Movement::new(self.speed.walk, self.speed.modes())
+    }
+}
+
+#[derive(Component, Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct HitPoints {
+    pub current: i32,
+    pub maximum: i32,
+}
+
+impl HitPoints {
+    pub fn new(maximum: i32) -> Self {
+        Self {
+            current: maximum,
+            maximum,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ability_modifiers_round_down() {
+        assert_eq!(AbilityScores::modifier(1), -5);
+        assert_eq!(AbilityScores::modifier(9), -1);
+        assert_eq!(AbilityScores::modifier(10), 0);
+        assert_eq!(AbilityScores::modifier(15), 2);
+        assert_eq!(AbilityScores::modifier(20), 5);
+    }
+
+    #[test]
+    fn every_creature_file_loads() {
+        for entry in std::fs::read_dir("resources/creatures").unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_str().unwrap();
+            let stat_block = StatBlock::load(name).unwrap();
+            assert!(stat_block.hit_points > 0, "{} has no hit points", name);
+        }
+    }
+
+    #[test]
+    fn damage_is_read_as_dice() {
+        assert_eq!(parse_dice("2d6+3"), Some(DiceType::new(2, 6, 3)));
+        assert_eq!(parse_dice("1d4 - 1"), Some(DiceType::new(1, 4, -1)));
+        assert_eq!(parse_dice("1d12"), Some(DiceType::new(1, 12, 0)));
+        for typo in ["2d6+", "2d", "d6", "2x6", "2d6+3+1", "0d6", "2d0", ""] {
+            assert_eq!(parse_dice(typo), None, "{}", typo);
+        }
+        let attack = |damage: &str| ron::from_str::<AttackProfile>(&format!(
+            "AttackProfile(name: \"Club\", to_hit: 2, damage: \"{}\", range: Melee(reach: 5))", damage
+        ));
+        let club = attack("1d4-1").unwrap();
+        assert_eq!(ron::from_str::<AttackProfile>(&ron::to_string(&club).unwrap()).unwrap(), club);
+        assert!(attack("2d6+").unwrap_err().to_string().contains("2d6+ isn't a dice expression"));
+    }
+
+    #[test]
+    fn missing_creature_files_are_errors() {
+        let error = StatBlock::load("gobbo").unwrap_err().to_string();
+        assert!(error.starts_with("Couldn't read resources/creatures/gobbo.ron"), "{}", error);
+    }
+}
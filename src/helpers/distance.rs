@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 use crate::prelude::*;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -9,6 +11,45 @@ pub enum Unit {
 
 pub const TILE_SIZE_IN_FEET: u16 = 5;
 
-pub fn distance_between_points(x: Point, y: Point) -> f32 {
-    DistanceAlg::Pythagoras.distance2d(x, y) * TILE_SIZE_IN_FEET as f32
-}
\ No newline at end of file
+// How diagonal steps are measured, chosen per encounter
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Hash)]
+pub enum DiagonalRule {
+    Chebyshev, // every step is 5 ft
+    #[default]
+    Alternating, // diagonals alternate between 5 ft and 10 ft
+    Euclidean, // diagonals are 5 ft times the square root of two
+}
+
+impl DiagonalRule {
+    // Feet to enter a tile that costs `cost` orthogonally. `odd_diagonals` is
+    // whether an odd number of diagonals has been taken before this step.
+    pub fn step_cost(&self, cost: u32, diagonal: bool, odd_diagonals: bool) -> u32 {
+        if !diagonal {
+            return cost;
+        }
+        match self {
+            DiagonalRule::Chebyshev => cost,
+            DiagonalRule::Alternating => if odd_diagonals { cost * 2 } else { cost },
+            DiagonalRule::Euclidean => (cost as f32 * std::f32::consts::SQRT_2).round() as u32,
+        }
+    }
+    // Average tiles walked by one diagonal step, as used by pathing heuristics
+    pub fn diagonal_tiles(&self) -> f32 {
+        match self {
+            DiagonalRule::Chebyshev => 1.0,
+            DiagonalRule::Alternating => 1.5,
+            DiagonalRule::Euclidean => std::f32::consts::SQRT_2,
+        }
+    }
+}
+
+pub fn distance_between_points(x: Point, y: Point, rule: DiagonalRule) -> f32 {
+    let dx = (x.x - y.x).abs();
+    let dy = (x.y - y.y).abs();
+    let tiles = match rule {
+        DiagonalRule::Chebyshev => dx.max(dy) as f32,
+        DiagonalRule::Alternating => (dx.max(dy) + dx.min(dy) / 2) as f32,
+        DiagonalRule::Euclidean => DistanceAlg::Pythagoras.distance2d(x, y),
+    };
+    tiles * TILE_SIZE_IN_FEET as f32
+}
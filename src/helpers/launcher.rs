@@ -0,0 +1,342 @@
+use std::fmt;
+
+use crate::helpers::distance::DiagonalRule;
+use crate::helpers::map_builder::{ArchitectKind, MapBuilder, custom::{check_registries, CustomFileBuilder, MapLoadError}, themes::{MapTheme, ThemeRegistry}};
+
+pub const USAGE: &str = "\
+Usage: adventure_encounters [OPTIONS]
+
+Options:
+  --map NAME             play assets/maps/NAME.map.ron [default: campfire]
+  --architect KIND       generate a map: rooms, drunkard, automata or random
+  --theme NAME           theme of a generated map, such as forest or dungeon
+  --size WIDTHxHEIGHT    size of a generated map [default: 80x50]
+  --diagonals RULE       how a generated map measures diagonal steps:
+                         chebyshev, alternating or euclidean
+                         [default: alternating]
+  --seed N               play a session again from its seed
+  --headless             let the AI play the encounter without a window
+                         and print how it went
+  --runs N               headless encounters to play, one seed after
+                         another [default: 1]
+  --rounds N             rounds before a headless encounter is called
+                         off [default: 20]
+  --help                 show this message
+
+A generated map rolls whatever isn't given from the seed. --map can't be
+combined with --architect, --theme, --size or --diagonals, as map files
+choose their own diagonal rule.";
+
+pub const DEFAULT_MAP: &str = "campfire";
+pub const DEFAULT_ROUNDS: u32 = 20;
+pub const DEFAULT_SIZE: (u32, u32) = (80, 50);
+// Smaller maps leave the architects no room to lay out rooms and monsters
+pub const MIN_SIZE: (u32, u32) = (40, 30);
+
+// Where the encounter's map comes from
+#[derive(Clone, Debug, PartialEq)]
+pub enum MapSource {
+    // An asset path, loaded and hot-reloaded through the asset server
+    File(String),
+    // A map generated from the seed, with None rolled from it too
+    Generated {
+        architect: Option<ArchitectKind>,
+        theme: Option<MapTheme>,
+        width: u32,
+        height: u32,
+        diagonal_rule: DiagonalRule,
+    },
+}
+
+impl MapSource {
+    pub fn map_file(name: &str) -> Self {
+        MapSource::File(format!("maps/{}.map.ron", name))
+    }
+    // Builds the map straight away, reading a map file from assets/ rather
+    // than through the asset server. Generated maps are built this way in
+    // the game too.
+    pub fn build(&self, seed: u64) -> Result<MapBuilder, MapLoadError> {
+        match self {
+            MapSource::File(path) => CustomFileBuilder::create_map_builder(format!("assets/{}", path)),
+            MapSource::Generated { architect, theme, width, height, diagonal_rule } => {
+                check_registries()?;
+                let mut mb = MapBuilder::new_generated(*architect, *theme, *width, *height, seed);
+                mb.map.diagonal_rule = *diagonal_rule;
+                Ok(mb)
+            }
+        }
+    }
+}
+
+// Encounters played by the AI without a window
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct HeadlessOptions {
+    pub runs: u32,
+    pub max_rounds: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UsageError {
+    Help,
+    Invalid(String),
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsageError::Help => write!(f, "{}", USAGE),
+            UsageError::Invalid(message) => write!(f, "{}\n\n{}", message, USAGE),
+        }
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+// What the game was asked to play from the command line
+#[derive(Clone, Debug, PartialEq)]
+pub struct LaunchOptions {
+    pub source: MapSource,
+    pub seed: Option<u64>, // None picks one at random
+    pub headless: Option<HeadlessOptions>,
+}
+
+impl LaunchOptions {
+    // Reads the arguments after the program name
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, UsageError> {
+        let mut map = None;
+        let mut architect = None;
+        let mut theme = None;
+        let mut size = None;
+        let mut diagonals = None;
+        let mut seed = None;
+        let mut headless = false;
+        let mut runs = None;
+        let mut rounds = None;
+
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--help" | "-h" => return Err(UsageError::Help),
+                "--headless" => {
+                    headless = true;
+                    continue;
+                }
+                _ => {}
+            }
+            let slot = match flag.as_str() {
+                "--map" => &mut map,
+                "--architect" => &mut architect,
+                "--theme" => &mut theme,
+                "--size" => &mut size,
+                "--diagonals" => &mut diagonals,
+                "--seed" => &mut seed,
+                "--runs" => &mut runs,
+                "--rounds" => &mut rounds,
+                _ => return Err(invalid(format!("Unknown option {}", flag))),
+            };
+            let Some(value) = args.next().filter(|value| !value.starts_with("--")) else {
+                return Err(invalid(format!("{} needs a value", flag)));
+            };
+            if slot.replace(value).is_some() {
+                return Err(invalid(format!("{} is given more than once", flag)));
+            }
+        }
+
+        let seed = match seed {
+            Some(seed) => Some(seed.parse().map_err(|_| invalid(format!("--seed takes a whole number, not {}", seed)))?),
+            None => None,
+        };
+        let headless = if headless {
+            Some(HeadlessOptions {
+                runs: parse_count("--runs", runs)?.unwrap_or(1),
+                max_rounds: parse_count("--rounds", rounds)?.unwrap_or(DEFAULT_ROUNDS),
+            })
+        } else {
+            for (flag, given) in [("--runs", &runs), ("--rounds", &rounds)] {
+                if given.is_some() {
+                    return Err(invalid(format!("{} only applies with --headless", flag)));
+                }
+            }
+            None
+        };
+        if let Some(name) = map {
+            for (flag, given) in [("--architect", &architect), ("--theme", &theme), ("--size", &size), ("--diagonals", &diagonals)] {
+                if given.is_some() {
+                    return Err(invalid(format!("--map plays a saved map, so it can't be combined with {}", flag)));
+                }
+            }
+            return Ok(Self { source: MapSource::map_file(&name), seed, headless });
+        }
+        if architect.is_none() && theme.is_none() && size.is_none() && diagonals.is_none() {
+            return Ok(Self { source: MapSource::map_file(DEFAULT_MAP), seed, headless });
+        }
+
+        let (width, height) = match size {
+            Some(size) => parse_size(&size)?,
+            None => DEFAULT_SIZE,
+        };
+        let source = MapSource::Generated {
+            architect: architect.map(|name| parse_architect(&name)).transpose()?.flatten(),
+            theme: theme.map(|name| parse_theme(&name)).transpose()?.flatten(),
+            width,
+            height,
+            diagonal_rule: diagonals.map(|name| parse_diagonals(&name)).transpose()?.unwrap_or_default(),
+        };
+        Ok(Self { source, seed, headless })
+    }
+}
+
+fn invalid(message: String) -> UsageError {
+    UsageError::Invalid(message)
+}
+
+fn parse_architect(name: &str) -> Result<Option<ArchitectKind>, UsageError> {
+    match name.to_lowercase().as_str() {
+        "rooms" => Ok(Some(ArchitectKind::Rooms)),
+        "drunkard" => Ok(Some(ArchitectKind::DrunkardsWalk)),
+        "automata" => Ok(Some(ArchitectKind::CellularAutomata)),
+        "random" => Ok(None),
+        _ => Err(invalid(format!("No architect named {}, try rooms, drunkard, automata or random", name))),
+    }
+}
+
+fn parse_diagonals(name: &str) -> Result<DiagonalRule, UsageError> {
+    match name.to_lowercase().as_str() {
+        "chebyshev" => Ok(DiagonalRule::Chebyshev),
+        "alternating" => Ok(DiagonalRule::Alternating),
+        "euclidean" => Ok(DiagonalRule::Euclidean),
+        _ => Err(invalid(format!("No diagonal rule named {}, try chebyshev, alternating or euclidean", name))),
+    }
+}
+
+// Themes are named as in resources/themes.ron, or without their Theme suffix
+fn parse_theme(name: &str) -> Result<Option<MapTheme>, UsageError> {
+    let wanted = name.to_lowercase();
+    if wanted == "random" {
+        return Ok(None);
+    }
+    let registry = ThemeRegistry::try_global().map_err(|error| invalid(error.to_string()))?;
+    let short_name = |theme: &MapTheme| {
+        let full = theme.name().to_lowercase();
+        full.strip_suffix("theme").map(str::to_string).unwrap_or(full)
+    };
+    if let Some(theme) = registry
+        .themes()
+        .find(|theme| theme.name().to_lowercase() == wanted || short_name(theme) == wanted)
+    {
+        return Ok(Some(theme));
+    }
+    let known: Vec<String> = registry.themes().map(|theme| short_name(&theme)).collect();
+    Err(invalid(format!("No theme named {}, try {} or random", name, known.join(", "))))
+}
+
+fn parse_count(flag: &str, count: Option<String>) -> Result<Option<u32>, UsageError> {
+    let Some(count) = count else {
+        return Ok(None);
+    };
+    match count.parse() {
+        Ok(count) if count > 0 => Ok(Some(count)),
+        _ => Err(invalid(format!("{} takes a whole number above zero, not {}", flag, count))),
+    }
+}
+
+fn parse_size(size: &str) -> Result<(u32, u32), UsageError> {
+    let parsed = size
+        .split_once(['x', 'X'])
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+    let Some((width, height)) = parsed else {
+        return Err(invalid(format!("--size takes WIDTHxHEIGHT, such as 80x50, not {}", size)));
+    };
+    if width < MIN_SIZE.0 || height < MIN_SIZE.1 {
+        return Err(invalid(format!("--size {} is too small, maps are at least {}x{}", size, MIN_SIZE.0, MIN_SIZE.1)));
+    }
+    Ok((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<LaunchOptions, UsageError> {
+        LaunchOptions::parse(args.split_whitespace().map(String::from))
+    }
+
+    fn message(args: &str) -> String {
+        match parse(args) {
+            Err(UsageError::Invalid(message)) => message,
+            other => panic!("{} should be a usage error, got {:?}", args, other),
+        }
+    }
+
+    #[test]
+    fn plays_the_campfire_map_by_default() {
+        let options = parse("").unwrap();
+        assert_eq!(options.source, MapSource::File("maps/campfire.map.ron".to_string()));
+        assert_eq!(options.seed, None);
+        assert_eq!(parse("--map arena --seed 7").unwrap().source, MapSource::File("maps/arena.map.ron".to_string()));
+        assert_eq!(parse("--seed 7").unwrap().seed, Some(7));
+    }
+
+    #[test]
+    fn generates_maps_from_the_options_given() {
+        let options = parse("--architect rooms --theme forest --size 60x40 --seed 3").unwrap();
+        assert_eq!(options.seed, Some(3));
+        assert_eq!(
+            options.source,
+            MapSource::Generated {
+                architect: Some(ArchitectKind::Rooms),
+                theme: Some(MapTheme::named("ForestTheme").unwrap()),
+                width: 60,
+                height: 40,
+                diagonal_rule: DiagonalRule::Alternating,
+            }
+        );
+        assert_eq!(
+            parse("--theme DungeonTheme").unwrap().source,
+            MapSource::Generated { architect: None, theme: Some(MapTheme::named("DungeonTheme").unwrap()), width: 80, height: 50, diagonal_rule: DiagonalRule::Alternating }
+        );
+        assert_eq!(
+            parse("--architect random").unwrap().source,
+            MapSource::Generated { architect: None, theme: None, width: 80, height: 50, diagonal_rule: DiagonalRule::Alternating }
+        );
+    }
+
+    #[test]
+    fn generated_maps_use_the_diagonal_rule_asked_for() {
+        let options = parse("--diagonals Euclidean --size 40x30").unwrap();
+        assert_eq!(
+            options.source,
+            MapSource::Generated { architect: None, theme: None, width: 40, height: 30, diagonal_rule: DiagonalRule::Euclidean }
+        );
+        assert_eq!(options.source.build(5).unwrap().map.diagonal_rule, DiagonalRule::Euclidean);
+        assert!(message("--map campfire --diagonals chebyshev").contains("--diagonals"));
+        assert!(message("--diagonals manhattan").starts_with("No diagonal rule named manhattan"));
+    }
+
+    #[test]
+    fn bad_combinations_are_usage_errors() {
+        assert_eq!(parse("--help"), Err(UsageError::Help));
+        assert!(message("--map campfire --architect rooms").contains("--architect"));
+        assert!(message("--map campfire --size 80x50").contains("--size"));
+        assert!(message("--architect mazes").starts_with("No architect named mazes"));
+        assert!(message("--theme swamp").contains("forest"));
+        assert!(message("--size 80").contains("WIDTHxHEIGHT"));
+        assert!(message("--size 20x10").contains("too small"));
+        assert!(message("--seed many").contains("whole number"));
+        assert!(message("--seed").contains("needs a value"));
+        assert!(message("--seed --map campfire").contains("needs a value"));
+        assert!(message("--seed 1 --seed 2").contains("more than once"));
+        assert!(message("--fast").starts_with("Unknown option"));
+    }
+
+    #[test]
+    fn headless_runs_take_their_own_options() {
+        assert_eq!(parse("").unwrap().headless, None);
+        assert_eq!(parse("--headless").unwrap().headless, Some(HeadlessOptions { runs: 1, max_rounds: 20 }));
+        let options = parse("--architect automata --headless --runs 10 --rounds 5").unwrap();
+        assert_eq!(options.headless, Some(HeadlessOptions { runs: 10, max_rounds: 5 }));
+        assert!(message("--runs 10").contains("only applies with --headless"));
+        assert!(message("--headless --rounds 0").contains("above zero"));
+        assert!(message("--headless --runs").contains("needs a value"));
+    }
+}
@@ -1,8 +1,25 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 use crate::prelude::*;
 
 use tiles::Tile;
-use tiles::TileType;
-use crate::map_builder::themes::MapTheme;
+use crate::helpers::map_builder::themes::{MapTheme, ThemeTile};
+use crate::helpers::creature::MovementModes;
+use crate::helpers::distance::{DiagonalRule, TILE_SIZE_IN_FEET, distance_between_points};
+
+// The eight directions a creature can step in, orthogonals first
+pub const STEP_DELTAS: [Point; 8] = [
+    Point { x: -1, y: 0 },
+    Point { x: 1, y: 0 },
+    Point { x: 0, y: -1 },
+    Point { x: 0, y: 1 },
+    Point { x: -1, y: -1 },
+    Point { x: 1, y: -1 },
+    Point { x: -1, y: 1 },
+    Point { x: 1, y: 1 },
+];
 
 #[derive(Component)]
 pub struct FogOfWarMapLayer;
@@ -13,7 +30,10 @@ pub struct ObjectsMapLayer;
 #[derive(Component)]
 pub struct FloorMapLayer;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Component)]
+pub struct MovementRangeMapLayer;
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
 pub enum SeenLevel {
     Bright,
     Shadowy,
@@ -21,6 +41,27 @@ pub enum SeenLevel {
     None
 }
 
+impl SeenLevel {
+    // Anything darker than shadowy leaves the viewer unable to make out a creature there
+    pub fn can_make_out(&self) -> bool {
+        matches!(self, SeenLevel::Bright | SeenLevel::Shadowy)
+    }
+}
+
+// Writes a set of points row by row, so the same state always saves the same way
+pub mod sorted_points {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(points: &HashSet<Point>, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut sorted: Vec<&Point> = points.iter().collect();
+        sorted.sort_by_key(|point| (point.y, point.x));
+        sorted.serialize(serializer)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashSet<Point>, D::Error> {
+        Ok(Vec::<Point>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
 #[derive(Component, Copy, Clone, PartialEq)]
 pub struct MapPoint(pub Point);
 
@@ -32,23 +73,27 @@ impl MapPoint {
     }
 }
 
+#[derive(Clone)]
 pub struct Map {
     pub dimensions: Point,
     pub tiles: Vec<Tile>,
-    pub revealed_tiles: Vec<SeenLevel>
+    pub revealed_tiles: Vec<SeenLevel>,
+    pub diagonal_rule: DiagonalRule
 }
 
 impl Map {
     pub fn new(width: u32, height: u32, theme: MapTheme) -> Self {
         let num_tiles = width * height;
         let mut tiles = Vec::with_capacity(num_tiles as usize);
+        let floor = Tile::new(theme.tile_to_render(ThemeTile::Floor));
         for _i in 0..num_tiles {
-            tiles.push(Tile::new(theme.tile_to_render(TileType::ThemeFloor)));
+            tiles.push(floor.clone());
         }
         Self {
             dimensions: Point { x: width as i32, y: height as i32 },
             tiles: tiles,
-            revealed_tiles: vec![SeenLevel::Darkness; num_tiles as usize]
+            revealed_tiles: vec![SeenLevel::Darkness; num_tiles as usize],
+            diagonal_rule: DiagonalRule::default()
         }
     }
     pub fn map_idx(&self, x: i32, y: i32) -> usize {
@@ -67,27 +112,41 @@ impl Map {
             y: (self.dimensions.y - y - 1) as u32,
         }
     }
+    pub fn from_bevy_ecs_tilemap(&self, tile_pos: &TilePos) -> Point {
+        Point::new(tile_pos.x as i32, self.dimensions.y - tile_pos.y as i32 - 1)
+    }
     fn valid_exit(&self, loc: Point, delta: Point) -> Option<usize> {
         let destination = loc + delta;
-        if self.in_bounds(destination) {
-            if self.can_enter_tile(destination) {
-                let idx = self.point2d_to_index(destination);
-                Some(idx)
-            } else {
-                None
-            }
+        if self.can_step(loc, delta, MovementModes::default()) {
+            let idx = self.point2d_to_index(destination);
+            Some(idx)
         } else {
             None
         }
     }
+    // Whether a creature at `loc` moving with `modes` can take one step by
+    // `delta`. Diagonal steps may not cut the corner of a tile it can't cross.
+    pub fn can_step(&self, loc: Point, delta: Point, modes: MovementModes) -> bool {
+        let destination = loc + delta;
+        if !self.in_bounds(destination) || self.tiles[self.map_idx(destination.x, destination.y)].movement_cost(modes).is_none() {
+            return false;
+        }
+        if delta.x != 0 && delta.y != 0 {
+            for corner in [Point::new(loc.x + delta.x, loc.y), Point::new(loc.x, loc.y + delta.y)] {
+                if self.tiles[self.map_idx(corner.x, corner.y)].movement_cost(modes).is_none() {
+                    return false;
+                }
+            }
+        }
+        true
+    }
     pub fn in_bounds(&self, point : Point) -> bool {
         point.x >= 0 && point.x < self.dimensions.x
             && point.y >= 0 && point.y < self.dimensions.y
     }
     pub fn can_enter_tile(&self, point : Point) -> bool {
-        self.in_bounds(point) && 
-            !self.tiles[self.map_idx(point.x, point.y)].is_opaque
-        
+        self.in_bounds(point) &&
+            !self.tiles[self.map_idx(point.x, point.y)].blocks_movement
     }
 
 }
@@ -103,32 +162,29 @@ impl Algorithm2D for Map {
 
 impl BaseMap for Map {
     fn is_opaque(&self, idx: usize) -> bool {
-        self.tiles[idx as usize].is_opaque
+        self.tiles[idx as usize].blocks_sight
     }
     fn get_available_exits(&self, idx: usize)
         -> SmallVec<[(usize, f32); 10]>
     {
         let mut exits = SmallVec::new();
         let location = self.index_to_point2d(idx);
-        if let Some(idx) = self.valid_exit(location, Point::new(-1, 0)) {
-            exits.push((idx, 1.0))
-        }
-        if let Some(idx) = self.valid_exit(location, Point::new(1, 0)) {
-            exits.push((idx, 1.0))
-        }
-        if let Some(idx) = self.valid_exit(location, Point::new(0, -1)) {
-            exits.push((idx, 1.0))
-        }
-        if let Some(idx) = self.valid_exit(location, Point::new(0, 1)) {
-            exits.push((idx, 1.0))
+        for delta in STEP_DELTAS {
+            if let Some(idx) = self.valid_exit(location, delta) {
+                if delta.x != 0 && delta.y != 0 {
+                    exits.push((idx, self.diagonal_rule.diagonal_tiles()))
+                } else {
+                    exits.push((idx, 1.0))
+                }
+            }
         }
         exits
     }
     fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
-        DistanceAlg::Pythagoras
-            .distance2d(
-                self.index_to_point2d(idx1),
-                self.index_to_point2d(idx2)
-            )
+        distance_between_points(
+            self.index_to_point2d(idx1),
+            self.index_to_point2d(idx2),
+            self.diagonal_rule
+        ) / TILE_SIZE_IN_FEET as f32
     }
 }
\ No newline at end of file
@@ -5,7 +5,8 @@ use super::MapBuilder;
 use super::Map;
 use super::MapTheme;
 use super::Tile;
-use super::TileType;
+use super::ThemeTile;
+use super::Legend;
 
 pub struct CellularAutomataArchitect {}
 
@@ -19,7 +20,9 @@ impl MapArchitect for CellularAutomataArchitect {
             entity_spawns: Vec::new(),
             player_start : Point::zero(),
             amulet_start : Point::zero(),
-            theme
+            theme,
+            legend: Legend::default(),
+            seed: None,
         };
         self.random_noise_map(rng, &mut mb);
         for _ in 0..10 {
@@ -42,9 +45,9 @@ impl CellularAutomataArchitect {
         mb.map.tiles.iter_mut().for_each(|t| {
             let roll = rng.range(0, 100);
             if roll > 55 {
-                *t = Tile::new(mb.theme.tile_to_render(TileType::ThemeFloor));
+                *t = Tile::new(mb.theme.tile_to_render(ThemeTile::Floor));
             } else {
-                *t = Tile::new(mb.theme.tile_to_render(TileType::ThemeWall));
+                *t = Tile::new(mb.theme.tile_to_render(ThemeTile::Wall));
             }
         });
     }    
@@ -57,7 +60,7 @@ impl CellularAutomataArchitect {
                     let x2 = x+ix;
                     let y2 = y+iy;
                     if mb.map.in_bounds(Point { x: x2, y: y2 })
-                        && mb.map.tiles[mb.map.map_idx(x2, y2)].tile_type == mb.theme.tile_to_render(TileType::ThemeWall) {
+                        && mb.map.tiles[mb.map.map_idx(x2, y2)].tile_type == mb.theme.tile_to_render(ThemeTile::Wall) {
                         neighbors += 1;
                     }
                 }
@@ -72,9 +75,9 @@ impl CellularAutomataArchitect {
                 let neighbors = self.count_neighbors(x, y, mb);
                 let idx = mb.map.map_idx(x, y);
                 if neighbors > 4 || neighbors == 0 {
-                    new_tiles[idx] = Tile::new(mb.theme.tile_to_render(TileType::ThemeWall));
+                    new_tiles[idx] = Tile::new(mb.theme.tile_to_render(ThemeTile::Wall));
                 } else {
-                    new_tiles[idx] = Tile::new(mb.theme.tile_to_render(TileType::ThemeFloor));
+                    new_tiles[idx] = Tile::new(mb.theme.tile_to_render(ThemeTile::Floor));
                 }
             }
         }
@@ -85,7 +88,7 @@ impl CellularAutomataArchitect {
         let closest_point = mb.map.tiles
             .iter()
             .enumerate()
-            .filter(|(_, t)| (**t).tile_type == mb.theme.tile_to_render(TileType::ThemeFloor))
+            .filter(|(_, t)| (**t).tile_type == mb.theme.tile_to_render(ThemeTile::Floor))
             .map(|(idx, _)| (idx, DistanceAlg::Pythagoras.distance2d(
                 center,
                 mb.map.index_to_point2d(idx)
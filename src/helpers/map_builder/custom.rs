@@ -1,57 +1,543 @@
-use std::fs::File;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
 
 use ron::de::from_reader;
+use ron::error::SpannedError;
+use ron::ser::PrettyConfig;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::prelude::{*, tiles::TileType};
+use crate::prelude::{*, creature::CreatureError, tiles::{Tile, TileRegistry, TileRegistryError, TileType}, distance::DiagonalRule, illumination::ProvidesIllumination};
 use crate::helpers::prelude::Map;
 
-use super::{themes::MapTheme, MapBuilder};
+use super::{themes::{MapTheme, ThemeRegistry, ThemeRegistryError, ThemeTile}, MapBuilder};
 
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename = "CustomMap")]
 pub struct CustomMap {
     pub width: i32,
     pub height: i32,
     pub theme: MapTheme,
-    pub player_start: (i32, i32),
+    // Left out when the legend has a PlayerStart glyph
+    #[serde(default, deserialize_with = "some_point", serialize_with = "bare_point", skip_serializing_if = "Option::is_none")]
+    pub player_start: Option<(i32, i32)>,
     pub amulet_start: (i32, i32),
+    #[serde(default)]
+    pub diagonal_rule: DiagonalRule,
+    // Added to, or replacing, the glyphs of the default legend
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub legend: BTreeMap<char, Glyph>,
+    // Spawns on top of whatever tile is there, as well as any from glyphs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub monster_spawns: Vec<(i32, i32)>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entity_spawns: Vec<((i32, i32), char)>,
+    // Written out by to_ron as a raw string, one line per row
+    #[serde(skip_serializing)]
     pub tiles: String
 }
 
+// Reads a bare `(x, y)`, as RON would otherwise want `Some((x, y))`
+fn some_point<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<(i32, i32)>, D::Error> {
+    <(i32, i32)>::deserialize(deserializer).map(Some)
+}
+
+fn bare_point<S: serde::Serializer>(point: &Option<(i32, i32)>, serializer: S) -> Result<S::Ok, S::Error> {
+    match point {
+        Some(point) => point.serialize(serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+// A light placed on the map, such as a campfire or a brazier
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct LightSource {
+    pub sprite_index: usize, // in assets/ground.png
+    pub bright_interval: u16,
+    pub shadowy_interval: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_magical: bool,
+}
+
+impl LightSource {
+    pub fn illumination(&self) -> ProvidesIllumination {
+        ProvidesIllumination {
+            is_magical: self.is_magical,
+            ..ProvidesIllumination::new(self.bright_interval, self.shadowy_interval, self.duration)
+        }
+    }
+}
+
+// What a character in a map's tile string stands for. Everything but Tile
+// and Wall stands on the theme's floor.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Glyph {
+    Tile(String), // by name, from resources/tiles.ron
+    Floor,
+    Wall,
+    Exit,
+    PlayerStart,
+    Monster, // picked from the theme's monster table
+    Light(LightSource),
+}
+
+// The glyphs a map's tile string is written with
+#[derive(Clone, Debug, PartialEq)]
+pub struct Legend(pub HashMap<char, Glyph>);
+
+impl Default for Legend {
+    fn default() -> Self {
+        let tile = |name: &str| Glyph::Tile(name.to_string());
+        Self(HashMap::from([
+            ('_', Glyph::Floor),
+            ('#', Glyph::Wall),
+            ('>', Glyph::Exit),
+            ('@', Glyph::PlayerStart),
+            ('M', Glyph::Monster),
+            ('C', Glyph::Light(LightSource { sprite_index: 135, bright_interval: 30, shadowy_interval: 60, duration: None, is_magical: false })),
+            ('=', tile("wall_stone_low")),
+            ('o', tile("barrel")),
+            ('|', tile("window")),
+            ('+', tile("portcullis")),
+            (':', tile("chasm")),
+            ('%', tile("curtain")),
+            ('~', tile("water_shallow")),
+            (';', tile("mud")),
+            ('*', tile("rubble")),
+            ('&', tile("undergrowth")),
+        ]))
+    }
+}
+
+impl Legend {
+    // The default legend with a map file's own glyphs laid over it
+    pub fn with(glyphs: &BTreeMap<char, Glyph>) -> Self {
+        let mut legend = Self::default();
+        legend.0.extend(glyphs.iter().map(|(c, glyph)| (*c, glyph.clone())));
+        legend
+    }
+    pub fn get(&self, c: char) -> Option<&Glyph> {
+        self.0.get(&c)
+    }
+    pub fn light(&self, c: char) -> Option<&LightSource> {
+        match self.get(c) {
+            Some(Glyph::Light(light)) => Some(light),
+            _ => None,
+        }
+    }
+}
+
+// Why a map couldn't be written out as a map file
+#[derive(Debug)]
+pub enum MapExportError {
+    NoGlyphFor(String), // the first tile left over once the spare glyphs ran out
+    Io(io::Error),
+    Write(ron::Error),
+}
+
+impl fmt::Display for MapExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapExportError::NoGlyphFor(name) => write!(f, "The map has too many kinds of tile to give {} a glyph", name),
+            MapExportError::Io(source) => write!(f, "{}", source),
+            MapExportError::Write(source) => write!(f, "Couldn't write the map: {}", source),
+        }
+    }
+}
+
+impl Error for MapExportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MapExportError::Io(source) => Some(source),
+            MapExportError::Write(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl CustomMap {
+    // The map a MapBuilder holds, written so that CustomFileBuilder builds it
+    // again. Tiles the legend has no glyph for get a spare one of their own.
+    pub fn from_map_builder(mb: &MapBuilder) -> Result<Self, MapExportError> {
+        let defaults = Legend::default();
+        let mut legend: BTreeMap<char, Glyph> = mb.legend.0
+            .iter()
+            .filter(|(c, glyph)| defaults.get(**c) != Some(*glyph))
+            .map(|(c, glyph)| (*c, glyph.clone()))
+            .collect();
+        let mut glyphs: HashMap<TileType, char> = HashMap::new();
+        for (c, glyph) in mb.legend.0.iter().collect::<BTreeMap<_, _>>() {
+            let tile_type = match glyph {
+                Glyph::Tile(name) => TileRegistry::global().find(name),
+                Glyph::Floor => Some(mb.theme.tile_to_render(ThemeTile::Floor)),
+                Glyph::Wall => Some(mb.theme.tile_to_render(ThemeTile::Wall)),
+                Glyph::Exit => Some(mb.theme.tile_to_render(ThemeTile::Exit)),
+                _ => None,
+            };
+            if let Some(tile_type) = tile_type {
+                glyphs.entry(tile_type).or_insert(*c);
+            }
+        }
+        let mut spare_glyphs = ('a'..='z')
+            .chain('A'..='Z')
+            .chain('0'..='9')
+            .chain("!$'(),-./<?[]^`{}".chars())
+            .filter(|c| !mb.legend.0.contains_key(c));
+        let mut tiles = String::new();
+        for row in mb.map.tiles.chunks(mb.map.dimensions.x as usize) {
+            tiles.push('\n');
+            for tile in row {
+                let glyph = match glyphs.get(&tile.tile_type) {
+                    Some(glyph) => *glyph,
+                    None => {
+                        let name = tile.tile_type.name().to_string();
+                        let glyph = spare_glyphs.next().ok_or_else(|| MapExportError::NoGlyphFor(name.clone()))?;
+                        legend.insert(glyph, Glyph::Tile(name));
+                        glyphs.insert(tile.tile_type, glyph);
+                        glyph
+                    }
+                };
+                tiles.push(glyph);
+            }
+        }
+        tiles.push('\n');
+        Ok(Self {
+            width: mb.map.dimensions.x,
+            height: mb.map.dimensions.y,
+            theme: mb.theme,
+            player_start: Some((mb.player_start.x, mb.player_start.y)),
+            amulet_start: (mb.amulet_start.x, mb.amulet_start.y),
+            diagonal_rule: mb.map.diagonal_rule,
+            legend,
+            monster_spawns: mb.monster_spawns.iter().map(|point| (point.x, point.y)).collect(),
+            entity_spawns: mb.entity_spawns.iter().map(|(point, c)| ((point.x, point.y), *c)).collect(),
+            tiles,
+        })
+    }
+    // The map as a RON file laid out like a hand-written one, with the tiles
+    // as a raw string so each row of the map is a line of the file
+    pub fn to_ron(&self) -> Result<String, MapExportError> {
+        let fields = ron::ser::to_string_pretty(self, PrettyConfig::new().struct_names(true))
+            .map_err(MapExportError::Write)?;
+        let fields = fields.trim_end().strip_suffix(')').expect("Custom maps serialize as a struct");
+        let hashes = (3..)
+            .map(|n| "#".repeat(n))
+            .find(|hashes| !self.tiles.contains(&format!("\"{}", hashes)))
+            .unwrap_or_default();
+        Ok(format!("{}    tiles: r{}\"{}\"{},\n)\n", fields, hashes, self.tiles, hashes))
+    }
+}
+
+// Splits a tile string into its glyphs, with the line and column each was
+// written at. Lines count from the first one holding tiles, and whitespace
+// is only there for layout.
+fn glyph_positions(tiles: &str) -> Vec<(char, usize, usize)> {
+    tiles
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .enumerate()
+        .flat_map(|(line, text)| {
+            text.chars()
+                .filter(|c| !c.is_whitespace())
+                .enumerate()
+                .map(move |(column, c)| (c, line + 1, column + 1))
+        })
+        .collect()
+}
+
+// Everything that can stop a custom map from loading
+#[derive(Debug)]
+pub enum MapLoadError {
+    Tiles(&'static TileRegistryError),
+    Themes(&'static ThemeRegistryError),
+    Creature(CreatureError),
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, source: SpannedError },
+    DimensionMismatch { width: i32, height: i32, tiles: usize },
+    UnknownGlyph { glyph: char, line: usize, column: usize },
+    UnknownTile { glyph: char, line: usize, column: usize, name: String },
+    NoPlayerStart,
+    SeveralPlayerStarts(usize),
+    StartOutOfBounds { start: &'static str, point: Point },
+    StartBlocked { start: &'static str, point: Point },
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapLoadError::Tiles(error) => write!(f, "{}", error),
+            MapLoadError::Themes(error) => write!(f, "{}", error),
+            MapLoadError::Creature(error) => write!(f, "{}", error),
+            MapLoadError::Io { path, source } => write!(f, "Couldn't read {}: {}", path.display(), source),
+            MapLoadError::Parse { path, source } => write!(f, "Couldn't parse {}: {}", path.display(), source),
+            MapLoadError::DimensionMismatch { width, height, tiles } => write!(
+                f, "Map has {} tiles but is {} by {}, which needs {}", tiles, width, height, width * height
+            ),
+            MapLoadError::UnknownGlyph { glyph, line, column } => write!(
+                f, "Unknown glyph '{}' at line {}, column {} of the map's tiles", glyph, line, column
+            ),
+            MapLoadError::UnknownTile { glyph, line, column, name } => write!(
+                f, "Glyph '{}' at line {}, column {} is an unknown tile {}", glyph, line, column, name
+            ),
+            MapLoadError::NoPlayerStart => write!(f, "Map needs a player_start or a player start glyph"),
+            MapLoadError::SeveralPlayerStarts(count) => write!(f, "Map has {} player starts but needs exactly one", count),
+            MapLoadError::StartOutOfBounds { start, point } => write!(
+                f, "{} ({}, {}) is off the map", start, point.x, point.y
+            ),
+            MapLoadError::StartBlocked { start, point } => write!(
+                f, "{} ({}, {}) is on a tile nothing can stand on", start, point.x, point.y
+            ),
+        }
+    }
+}
+
+impl Error for MapLoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MapLoadError::Tiles(error) => Some(*error),
+            MapLoadError::Themes(error) => Some(*error),
+            MapLoadError::Creature(error) => Some(error),
+            MapLoadError::Io { source, .. } => Some(source),
+            MapLoadError::Parse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// Maps can only be built once the tiles and themes they're made of have loaded
+pub fn check_registries() -> Result<(), MapLoadError> {
+    TileRegistry::try_global().map_err(MapLoadError::Tiles)?;
+    ThemeRegistry::try_global().map_err(MapLoadError::Themes)?;
+    Ok(())
+}
+
 pub struct CustomFileBuilder {}
 
 impl CustomFileBuilder {
-    pub fn create_map_builder(file_name: String) -> MapBuilder {
-        let path = format!("resources/{}.ron", file_name);
-        let file = File::open(path).expect("Failed opening file");
-        let custom_map: CustomMap = from_reader(file).expect("Unable to load templates");
-        let map = Map::new(custom_map.width as u32, custom_map.height as u32, custom_map.theme);
-        let tiles: String = custom_map.tiles.chars().filter(|c| !c.is_whitespace()).collect();
-        let mut entity_spawns = Vec::new();
-        for (i, c) in tiles.chars().enumerate() {
-            // TODO iterate over chars
-            let point = map.index_to_point2d(i);
-            match c {
-                'C' => entity_spawns.push((point, c)),
-                _ => {}
-            }
+    pub fn create_map_builder(path: impl AsRef<Path>) -> Result<MapBuilder, MapLoadError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|source| MapLoadError::Io { path: path.to_path_buf(), source })?;
+        let custom_map: CustomMap = from_reader(file)
+            .map_err(|source| MapLoadError::Parse { path: path.to_path_buf(), source })?;
+        Self::build(&custom_map)
+    }
+    pub fn save(mb: &MapBuilder, path: impl AsRef<Path>) -> Result<(), MapExportError> {
+        fs::write(path, CustomMap::from_map_builder(mb)?.to_ron()?).map_err(MapExportError::Io)
+    }
+    pub fn build(custom_map: &CustomMap) -> Result<MapBuilder, MapLoadError> {
+        check_registries()?;
+        let legend = Legend::with(&custom_map.legend);
+        let glyphs = glyph_positions(&custom_map.tiles);
+        if custom_map.width <= 0 || custom_map.height <= 0
+            || glyphs.len() != (custom_map.width * custom_map.height) as usize {
+            return Err(MapLoadError::DimensionMismatch {
+                width: custom_map.width,
+                height: custom_map.height,
+                tiles: glyphs.len(),
+            });
         }
-        let mut mb = MapBuilder{
+        let mut map = Map::new(custom_map.width as u32, custom_map.height as u32, custom_map.theme);
+        map.diagonal_rule = custom_map.diagonal_rule;
+        let mut mb = MapBuilder {
             map,
             rooms: Vec::new(),
-            monster_spawns : Vec::new(),
-            entity_spawns: entity_spawns,
-            player_start : Point::new(custom_map.player_start.0, custom_map.player_start.1),
-            amulet_start : Point::new(custom_map.amulet_start.0, custom_map.amulet_start.1),
-            theme: custom_map.theme
+            monster_spawns: Vec::new(),
+            entity_spawns: Vec::new(),
+            player_start: Point::zero(),
+            amulet_start: Point::new(custom_map.amulet_start.0, custom_map.amulet_start.1),
+            theme: custom_map.theme,
+            legend,
+            seed: None,
+        };
+        let mut player_starts = Vec::new();
+        let theme_tile = |tile: ThemeTile| Tile::new(custom_map.theme.tile_to_render(tile));
+        for (i, (glyph, line, column)) in glyphs.into_iter().enumerate() {
+            let point = mb.map.index_to_point2d(i);
+            mb.map.tiles[i] = match mb.legend.get(glyph).cloned() {
+                None => return Err(MapLoadError::UnknownGlyph { glyph, line, column }),
+                Some(Glyph::Tile(name)) => match TileRegistry::global().find(&name) {
+                    Some(tile_type) => Tile::new(tile_type),
+                    None => return Err(MapLoadError::UnknownTile { glyph, line, column, name }),
+                },
+                Some(Glyph::Wall) => theme_tile(ThemeTile::Wall),
+                Some(Glyph::Exit) => theme_tile(ThemeTile::Exit),
+                Some(Glyph::Floor) => theme_tile(ThemeTile::Floor),
+                Some(Glyph::PlayerStart) => {
+                    player_starts.push(point);
+                    theme_tile(ThemeTile::Floor)
+                }
+                Some(Glyph::Monster) => {
+                    mb.monster_spawns.push(point);
+                    theme_tile(ThemeTile::Floor)
+                }
+                Some(Glyph::Light(_)) => {
+                    mb.entity_spawns.push((point, glyph));
+                    theme_tile(ThemeTile::Floor)
+                }
+            };
+        }
+        mb.monster_spawns.extend(custom_map.monster_spawns.iter().map(|(x, y)| Point::new(*x, *y)));
+        mb.entity_spawns.extend(custom_map.entity_spawns.iter().map(|((x, y), c)| (Point::new(*x, *y), *c)));
+        for point in mb.monster_spawns.iter().chain(mb.entity_spawns.iter().map(|(point, _)| point)) {
+            if !mb.map.in_bounds(*point) {
+                return Err(MapLoadError::StartOutOfBounds { start: "A spawn", point: *point });
+            }
+        }
+        mb.player_start = match (custom_map.player_start, player_starts.as_slice()) {
+            (Some((x, y)), []) => Point::new(x, y),
+            (None, [start]) => *start,
+            (None, []) => return Err(MapLoadError::NoPlayerStart),
+            (from_file, _) => {
+                return Err(MapLoadError::SeveralPlayerStarts(player_starts.len() + from_file.iter().count()))
+            }
         };
-        mb.fill(TileType::ThemeFloor);
-        // mb.player_start = mb.rooms[0].center();
-        // mb.amulet_start = mb.find_most_distant();
-        // for room in mb.rooms.iter().skip(1) {
-        //     mb.monster_spawns.push(room.center());
-        // }
-        mb
-    }
-}
\ No newline at end of file
+        for (start, point) in [("player_start", mb.player_start), ("amulet_start", mb.amulet_start)] {
+            if !mb.map.in_bounds(point) {
+                return Err(MapLoadError::StartOutOfBounds { start, point });
+            }
+            if !mb.map.can_enter_tile(point) {
+                return Err(MapLoadError::StartBlocked { start, point });
+            }
+        }
+        Ok(mb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tiles::TileType;
+
+    fn custom_map(width: i32, height: i32, legend: &str, tiles: &str) -> CustomMap {
+        ron::from_str(&format!(
+            "(width: {}, height: {}, theme: DungeonTheme, amulet_start: (2, 0), legend: {{{}}}, tiles: \"{}\")",
+            width, height, legend, tiles
+        ))
+        .unwrap()
+    }
+
+    fn load_error(custom_map: &CustomMap) -> MapLoadError {
+        match CustomFileBuilder::build(custom_map) {
+            Ok(_) => panic!("Map loaded"),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn glyphs_place_tiles_starts_monsters_and_lights() {
+        let map = custom_map(4, 2, "'!': Tile(\"barrel\")", "\n  #@_!\n  M>C_\n");
+        let mb = CustomFileBuilder::build(&map).unwrap();
+        let theme = MapTheme::named("DungeonTheme").unwrap();
+        assert_eq!(mb.map.tiles[0].tile_type, theme.tile_to_render(ThemeTile::Wall));
+        assert_eq!(mb.map.tiles[3].tile_type, TileType::named("barrel").unwrap());
+        assert_eq!(mb.map.tiles[5].tile_type, theme.tile_to_render(ThemeTile::Exit));
+        assert_eq!(mb.player_start, Point::new(1, 0));
+        assert_eq!(mb.monster_spawns, vec![Point::new(0, 1)]);
+        assert_eq!(mb.entity_spawns, vec![(Point::new(2, 1), 'C')]);
+        assert!(mb.legend.light('C').is_some());
+    }
+
+    #[test]
+    fn campfire_loads_with_the_default_legend() {
+        let mb = CustomFileBuilder::create_map_builder("assets/maps/campfire.map.ron").unwrap();
+        assert_eq!(mb.player_start, Point::new(20, 5));
+        assert_eq!(mb.entity_spawns.len(), 2);
+        assert_eq!(mb.map.tiles[mb.map.map_idx(8, 19)].tile_type, TileType::named("chasm").unwrap());
+    }
+
+    #[test]
+    fn broken_files_are_errors() {
+        assert!(matches!(
+            CustomFileBuilder::create_map_builder("resources/no_such_map.ron"),
+            Err(MapLoadError::Io { .. })
+        ));
+        assert!(matches!(
+            CustomFileBuilder::create_map_builder("resources/tiles.ron"),
+            Err(MapLoadError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn tile_count_must_match_the_size() {
+        let error = load_error(&custom_map(4, 2, "", "#@__\n___"));
+        assert_eq!(error.to_string(), "Map has 7 tiles but is 4 by 2, which needs 8");
+    }
+
+    #[test]
+    fn unknown_glyphs_give_their_line_and_column() {
+        let error = load_error(&custom_map(4, 2, "", "\n#@__\n__?_\n"));
+        assert!(matches!(error, MapLoadError::UnknownGlyph { glyph: '?', line: 2, column: 3 }), "{}", error);
+        let error = load_error(&custom_map(4, 2, "'?': Tile(\"lava\")", "#@__\n__?_"));
+        assert!(matches!(error, MapLoadError::UnknownTile { glyph: '?', line: 2, column: 3, .. }), "{}", error);
+    }
+
+    #[test]
+    fn starts_must_be_on_the_map_and_standable() {
+        let error = load_error(&custom_map(4, 2, "", "#___\n____"));
+        assert!(matches!(error, MapLoadError::NoPlayerStart), "{}", error);
+        let error = load_error(&custom_map(4, 2, "", "#@__\n_@__"));
+        assert!(matches!(error, MapLoadError::SeveralPlayerStarts(2)), "{}", error);
+        let error = load_error(&custom_map(4, 2, "", "#@#_\n____"));
+        assert!(matches!(error, MapLoadError::StartBlocked { start: "amulet_start", .. }), "{}", error);
+        let error = load_error(&custom_map(2, 2, "", "@_\n__"));
+        assert!(matches!(error, MapLoadError::StartOutOfBounds { start: "amulet_start", .. }), "{}", error);
+    }
+
+    fn assert_same_map(a: &MapBuilder, b: &MapBuilder) {
+        let tile_types = |mb: &MapBuilder| mb.map.tiles.iter().map(|tile| tile.tile_type).collect::<Vec<_>>();
+        assert_eq!(a.map.dimensions, b.map.dimensions);
+        assert!(tile_types(a) == tile_types(b));
+        assert_eq!(a.theme, b.theme);
+        assert_eq!(a.map.diagonal_rule, b.map.diagonal_rule);
+        assert_eq!(a.player_start, b.player_start);
+        assert_eq!(a.amulet_start, b.amulet_start);
+        assert_eq!(a.monster_spawns, b.monster_spawns);
+        assert_eq!(a.entity_spawns, b.entity_spawns);
+    }
+
+    fn round_trip(mb: &MapBuilder) -> MapBuilder {
+        let text = CustomMap::from_map_builder(mb).unwrap().to_ron().unwrap();
+        let custom_map: CustomMap = ron::from_str(&text).unwrap_or_else(|error| panic!("{}\n{}", error, text));
+        CustomFileBuilder::build(&custom_map).unwrap_or_else(|error| panic!("{}\n{}", error, text))
+    }
+
+    #[test]
+    fn generated_maps_round_trip() {
+        use crate::helpers::map_builder::ArchitectKind;
+        for (seed, kind) in ArchitectKind::ALL.into_iter().enumerate() {
+            for theme in ["DungeonTheme", "ForestTheme"] {
+                let mb = MapBuilder::new(kind.architect(), MapTheme::named(theme).unwrap(), 80, 50, seed as u64);
+                assert_same_map(&mb, &round_trip(&mb));
+            }
+        }
+    }
+
+    #[test]
+    fn hand_written_maps_round_trip() {
+        let mb = CustomFileBuilder::create_map_builder("assets/maps/campfire.map.ron").unwrap();
+        let copy = round_trip(&mb);
+        assert_same_map(&mb, &copy);
+        assert!(copy.legend.light('C').is_some());
+        let text = CustomMap::from_map_builder(&mb).unwrap().to_ron().unwrap();
+        assert!(text.contains("\n___________=====______________|%+|___"), "{}", text);
+    }
+
+    #[test]
+    fn tiles_left_without_a_glyph_are_an_error() {
+        let theme = MapTheme::named("DungeonTheme").unwrap();
+        let mut mb = MapBuilder::from_map(Map::new(2, 1, theme), theme);
+        mb.map.tiles[1] = Tile::named("fog").unwrap();
+        // Every glyph a tile could be given is taken
+        for c in '!'..='~' {
+            mb.legend.0.entry(c).or_insert(Glyph::Monster);
+        }
+        let error = CustomMap::from_map_builder(&mb).unwrap_err();
+        assert!(matches!(&error, MapExportError::NoGlyphFor(name) if name == "fog"), "{}", error);
+    }
+}
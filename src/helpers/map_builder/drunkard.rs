@@ -5,7 +5,8 @@ use super::MapBuilder;
 use super::Map;
 use super::MapTheme;
 use super::Tile;
-use super::TileType;
+use super::ThemeTile;
+use super::Legend;
 
 const STAGGER_DISTANCE: usize = 400;
 
@@ -20,9 +21,11 @@ impl MapArchitect for DrunkardsWalkArchitect {
             entity_spawns: Vec::new(),
             player_start: Point::zero(),
             amulet_start: Point::zero(),
-            theme
+            theme,
+            legend: Legend::default(),
+            seed: None,
         };
-        mb.fill(TileType::ThemeWall);
+        mb.fill(ThemeTile::Wall);
         let center = Point::new(width / 2, height / 2);
         let desired_floor = (width * height) / 3;
         self.drunkard(&center, rng, &mut mb);
@@ -30,7 +33,7 @@ impl MapArchitect for DrunkardsWalkArchitect {
             .map
             .tiles
             .iter()
-            .filter(|t| (**t).tile_type == mb.theme.tile_to_render(TileType::ThemeFloor))
+            .filter(|t| (**t).tile_type == mb.theme.tile_to_render(ThemeTile::Floor))
             .count()
             < desired_floor as usize
         {
@@ -51,7 +54,7 @@ impl MapArchitect for DrunkardsWalkArchitect {
                 .iter()
                 .enumerate()
                 .filter(|(_, distance)| *distance > &2000.0)
-                .for_each(|(idx, _)| mb.map.tiles[idx] = Tile::new(mb.theme.tile_to_render(TileType::ThemeWall)));
+                .for_each(|(idx, _)| mb.map.tiles[idx] = Tile::new(mb.theme.tile_to_render(ThemeTile::Wall)));
         }
         mb.monster_spawns = mb.spawn_monsters(&center, rng);
         mb.player_start = center;
@@ -66,7 +69,7 @@ impl DrunkardsWalkArchitect {
         let mut distance_staggered = 0;
         loop {
             let drunk_idx = mb.map.point2d_to_index(drunkard_pos);
-            mb.map.tiles[drunk_idx] = Tile::new(mb.theme.tile_to_render(TileType::ThemeFloor));
+            mb.map.tiles[drunk_idx] = Tile::new(mb.theme.tile_to_render(ThemeTile::Floor));
             match rng.range(0, 4) {
                 0 => drunkard_pos.x -= 1,
                 1 => drunkard_pos.x += 1,
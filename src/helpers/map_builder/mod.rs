@@ -10,18 +10,40 @@ pub mod themes;
 
 // use empty::EmptyArchitect;
 use automata::CellularAutomataArchitect;
+use custom::Legend;
 use drunkard::DrunkardsWalkArchitect;
 use prefab::apply_prefab;
 use rooms::RoomsArchitect;
 use themes::*;
 
-use crate::map::*;
-use crate::tiles::*;
+use crate::helpers::map::*;
+use crate::helpers::tiles::*;
 
 pub trait MapArchitect {
     fn new(&mut self, width: u32, height: u32, theme: MapTheme, rng: &mut RandomNumberGenerator) -> MapBuilder;
 }
 
+// The architects a map can be generated with, in the order a seed rolls them
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ArchitectKind {
+    DrunkardsWalk,
+    Rooms,
+    CellularAutomata,
+}
+
+impl ArchitectKind {
+    // Every architect, for rolling one and for tests that try them all
+    pub const ALL: [ArchitectKind; 3] = [ArchitectKind::DrunkardsWalk, ArchitectKind::Rooms, ArchitectKind::CellularAutomata];
+
+    pub fn architect(&self) -> Box<dyn MapArchitect> {
+        match self {
+            ArchitectKind::DrunkardsWalk => Box::new(DrunkardsWalkArchitect {}),
+            ArchitectKind::Rooms => Box::new(RoomsArchitect {}),
+            ArchitectKind::CellularAutomata => Box::new(CellularAutomataArchitect {}),
+        }
+    }
+}
+
 
 #[derive(Resource)]
 pub struct MapBuilder {
@@ -31,30 +53,79 @@ pub struct MapBuilder {
     pub entity_spawns: Vec<(Point, char)>,
     pub player_start: Point,
     pub amulet_start: Point,
-    pub theme : MapTheme
+    pub theme : MapTheme,
+    pub legend: Legend, // what the glyphs of entity_spawns stand for
+    pub seed: Option<u64>, // what a generated map was built from
 }
 
 impl MapBuilder {
-    pub fn new_random(width: u32, height: u32, rng: &mut RandomNumberGenerator) -> Self {
-        let architect: Box<dyn MapArchitect> = match rng.range(0, 3) {
-            0 => Box::new(DrunkardsWalkArchitect {}),
-            1 => Box::new(RoomsArchitect {}),
-            _ => Box::new(CellularAutomataArchitect {}),
-        };
-        let theme = match rng.range(0, 2) {
-            0 => MapTheme::DungeonTheme,
-            _ => MapTheme::ForestTheme
-        };
-        Self::new(architect, theme, width, height, rng)
+    // A hand-built map with nothing placed on it yet
+    pub fn from_map(map: Map, theme: MapTheme) -> Self {
+        Self {
+            map,
+            rooms: Vec::new(),
+            monster_spawns: Vec::new(),
+            entity_spawns: Vec::new(),
+            player_start: Point::zero(),
+            amulet_start: Point::zero(),
+            theme,
+            legend: Legend::default(),
+            seed: None,
+        }
+    }
+    pub fn new_random(width: u32, height: u32, seed: u64) -> Self {
+        Self::new_generated(None, None, width, height, seed)
+    }
+    // Rolls whichever of the architect and theme isn't given from the seed.
+    // Both are always rolled, so giving one doesn't change the other.
+    pub fn new_generated(architect: Option<ArchitectKind>, theme: Option<MapTheme>, width: u32, height: u32, seed: u64) -> Self {
+        let mut rng = RandomNumberGenerator::seeded(seed);
+        let rolled_architect = ArchitectKind::ALL[rng.range(0, ArchitectKind::ALL.len() as i32) as usize];
+        let themes: Vec<MapTheme> = ThemeRegistry::global().themes().collect();
+        let rolled_theme = themes[rng.random_slice_index(&themes).unwrap()];
+        Self::new(
+            architect.unwrap_or(rolled_architect).architect(),
+            theme.unwrap_or(rolled_theme),
+            width,
+            height,
+            seed,
+        )
     }
-    pub fn new(mut architect: Box<dyn MapArchitect>, theme: MapTheme, width: u32, height: u32, rng: &mut RandomNumberGenerator) -> Self {
-        let mut mb = architect.new(width, height, theme, rng);
-        apply_prefab(&mut mb, rng);
+    // The same architect, theme, size and seed always build the same map
+    pub fn new(mut architect: Box<dyn MapArchitect>, theme: MapTheme, width: u32, height: u32, seed: u64) -> Self {
+        let mut rng = RandomNumberGenerator::seeded(seed);
+        let mut mb = architect.new(width, height, theme, &mut rng);
+        apply_prefab(&mut mb, &mut rng);
         mb.theme = theme;
+        mb.decorate(&mut rng);
+        mb.seed = Some(seed);
         mb
     }
-    fn fill(&mut self, tile: TileType) {
-        self.map.tiles.iter_mut().for_each(|t| *t = Tile::new(self.theme.tile_to_render(tile)));
+    // Architects lay the map out with the theme's main floor and wall. This
+    // re-rolls those tiles from the theme's weighted lists and strews
+    // decorations over open floor away from where anything starts.
+    fn decorate(&mut self, rng: &mut RandomNumberGenerator) {
+        let floor = self.theme.tile_to_render(ThemeTile::Floor);
+        let wall = self.theme.tile_to_render(ThemeTile::Wall);
+        for idx in 0..self.map.tiles.len() {
+            let point = self.map.index_to_point2d(idx);
+            let tile_type = self.map.tiles[idx].tile_type;
+            let occupied = point == self.player_start
+                || point == self.amulet_start
+                || self.monster_spawns.contains(&point)
+                || self.entity_spawns.iter().any(|(spawn, _)| *spawn == point);
+            if tile_type == floor {
+                let decoration = if occupied { None } else { self.theme.pick_decoration(rng) };
+                let tile_type = decoration.unwrap_or_else(|| self.theme.pick(ThemeTile::Floor, rng));
+                self.map.tiles[idx] = Tile::new(tile_type);
+            } else if tile_type == wall {
+                self.map.tiles[idx] = Tile::new(self.theme.pick(ThemeTile::Wall, rng));
+            }
+        }
+    }
+    fn fill(&mut self, tile: ThemeTile) {
+        let tile = Tile::new(self.theme.tile_to_render(tile));
+        self.map.tiles.iter_mut().for_each(|t| *t = tile.clone());
     }
     fn find_most_distant(&self) -> Point {
         let dijkstra_map = DijkstraMap::new(
@@ -84,7 +155,7 @@ impl MapBuilder {
             .iter()
             .enumerate()
             .filter(|(idx, t)| {
-                (**t).tile_type == self.theme.tile_to_render(TileType::ThemeFloor)
+                (**t).tile_type == self.theme.tile_to_render(ThemeTile::Floor)
                     && DistanceAlg::Pythagoras.distance2d(*start, self.map.index_to_point2d(*idx))
                         > 10.0
             })
@@ -99,3 +170,47 @@ impl MapBuilder {
         spawns
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use custom::CustomMap;
+
+    // Every byte of the map as it would be saved
+    fn fingerprint(mb: &MapBuilder) -> String {
+        CustomMap::from_map_builder(mb).unwrap().to_ron().unwrap()
+    }
+
+    #[test]
+    fn every_architect_builds_the_same_map_from_the_same_seed() {
+        for kind in ArchitectKind::ALL {
+            for theme in ThemeRegistry::global().themes() {
+                let first = MapBuilder::new(kind.architect(), theme, 80, 50, 1234);
+                let again = MapBuilder::new(kind.architect(), theme, 80, 50, 1234);
+                let other = MapBuilder::new(kind.architect(), theme, 80, 50, 4321);
+                assert_eq!(first.seed, Some(1234));
+                assert_eq!(fingerprint(&first), fingerprint(&again));
+                assert_ne!(fingerprint(&first), fingerprint(&other));
+            }
+        }
+    }
+
+    #[test]
+    fn random_maps_are_chosen_by_the_seed_too() {
+        for seed in 0..6 {
+            let first = MapBuilder::new_random(60, 40, seed);
+            assert_eq!(fingerprint(&first), fingerprint(&MapBuilder::new_random(60, 40, seed)));
+        }
+    }
+
+    #[test]
+    fn choosing_the_architect_keeps_the_rolled_theme() {
+        for seed in 0..6 {
+            let random = MapBuilder::new_random(60, 40, seed);
+            for kind in ArchitectKind::ALL {
+                let chosen = MapBuilder::new_generated(Some(kind), None, 60, 40, seed);
+                assert_eq!(chosen.theme, random.theme);
+            }
+        }
+    }
+}
@@ -2,7 +2,7 @@ use crate::prelude::*;
 
 use super::MapBuilder;
 use super::Tile;
-use super::TileType;
+use super::ThemeTile;
 
 const FORTRESS: (&str, i32, i32) = (
     "
@@ -65,11 +65,11 @@ pub fn apply_prefab(mb: &mut MapBuilder, rng: &mut RandomNumberGenerator) {
                 let c = string_vec[i];
                 match c {
                     'M' => {
-                        mb.map.tiles[idx] = Tile::new(mb.theme.tile_to_render(TileType::ThemeFloor));
+                        mb.map.tiles[idx] = Tile::new(mb.theme.tile_to_render(ThemeTile::Floor));
                         mb.monster_spawns.push(Point::new(tx, ty));
                     }
-                    '-' => mb.map.tiles[idx] = Tile::new(mb.theme.tile_to_render(TileType::ThemeFloor)),
-                    '#' => mb.map.tiles[idx] = Tile::new(mb.theme.tile_to_render(TileType::ThemeWall)),
+                    '-' => mb.map.tiles[idx] = Tile::new(mb.theme.tile_to_render(ThemeTile::Floor)),
+                    '#' => mb.map.tiles[idx] = Tile::new(mb.theme.tile_to_render(ThemeTile::Wall)),
                     _ => println!("No idea what to do with [{}]", c)
                 }
                 i += 1;
@@ -4,7 +4,8 @@ use super::Map;
 use super::MapArchitect;
 use super::MapBuilder;
 use super::MapTheme;
-use super::TileType;
+use super::ThemeTile;
+use super::Legend;
 use crate::helpers::prelude::Tile;
 
 const NUM_ROOMS: usize = 20;
@@ -22,8 +23,10 @@ impl MapArchitect for RoomsArchitect {
             player_start : Point::zero(),
             amulet_start : Point::zero(),
             theme,
+            legend: Legend::default(),
+            seed: None,
         };
-        mb.fill(TileType::ThemeWall);
+        mb.fill(ThemeTile::Wall);
         self.build_random_rooms(&mut mb, rng);
         self.build_corridors(&mut mb, rng);
         mb.player_start = mb.rooms[0].center();
@@ -54,7 +57,7 @@ impl RoomsArchitect {
                 room.for_each(|p| {
                     if p.x > 0 && p.x < mb.map.dimensions.x && p.y > 0 && p.y < mb.map.dimensions.y {
                         let idx = mb.map.map_idx(p.x, p.y);
-                        mb.map.tiles[idx] = Tile::new(mb.theme.tile_to_render(TileType::ThemeFloor));
+                        mb.map.tiles[idx] = Tile::new(mb.theme.tile_to_render(ThemeTile::Floor));
                     }
                 });
                 mb.rooms.push(room)
@@ -65,7 +68,7 @@ impl RoomsArchitect {
         use std::cmp::{max, min};
         for y in min(y1, y2)..=max(y1, y2) {
             if let Some(idx) = mb.map.try_idx(Point::new(x, y)) {
-                mb.map.tiles[idx as usize] = Tile::new(mb.theme.tile_to_render(TileType::ThemeFloor));
+                mb.map.tiles[idx as usize] = Tile::new(mb.theme.tile_to_render(ThemeTile::Floor));
             }
         }
     }
@@ -73,7 +76,7 @@ impl RoomsArchitect {
         use std::cmp::{max, min};
         for x in min(x1, x2)..=max(x1, x2) {
             if let Some(idx) = mb.map.try_idx(Point::new(x, y)) {
-                mb.map.tiles[idx as usize] = Tile::new(mb.theme.tile_to_render(TileType::ThemeFloor));
+                mb.map.tiles[idx as usize] = Tile::new(mb.theme.tile_to_render(ThemeTile::Floor));
             }
         }
     }
@@ -1,33 +1,365 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::OnceLock;
 
-use crate::tiles::*;
+use bracket_random::prelude::RandomNumberGenerator;
 
-#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
-pub enum MapTheme {
-    ForestTheme,
-    DungeonTheme,
+use ron::error::SpannedError;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::helpers::creature::{CreatureError, StatBlock};
+use crate::helpers::rng::pick_weighted;
+use crate::helpers::tiles::*;
+
+const THEME_REGISTRY_PATH: &str = "resources/themes.ron";
+
+static THEME_REGISTRY: OnceLock<Result<ThemeRegistry, ThemeRegistryError>> = OnceLock::new();
+
+// The parts of a map each theme picks its own tiles for
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ThemeTile {
+    Floor,
+    Wall,
+    Exit,
 }
 
-impl MapTheme {
-    pub fn tile_to_render(&self, tile_type: TileType) -> TileType {
+// A theme as written in resources/themes.ron, with tiles by name
+#[derive(Clone, Debug, Deserialize)]
+struct ThemeFile {
+    name: String,
+    floors: Vec<(String, i32)>,
+    walls: Vec<(String, i32)>,
+    exits: Vec<(String, i32)>,
+    #[serde(default)]
+    decorations: Vec<(String, i32)>,
+    #[serde(default)]
+    decoration_percent: i32,
+    monsters: Vec<(String, i32)>,
+}
+
+// Weighted lists of tiles and monsters that give a map its look
+#[derive(Clone, Debug)]
+pub struct ThemeDefinition {
+    pub name: String,
+    pub floors: Vec<(TileType, i32)>,
+    pub walls: Vec<(TileType, i32)>,
+    pub exits: Vec<(TileType, i32)>,
+    pub decorations: Vec<(TileType, i32)>,
+    pub decoration_percent: i32,
+    pub monsters: Vec<(String, i32)>, // creature files
+}
+
+// Why resources/themes.ron couldn't be loaded
+#[derive(Debug)]
+pub enum ThemeRegistryError {
+    Tiles(&'static TileRegistryError),
+    Io(io::Error),
+    Parse(SpannedError),
+    DefinedTwice(String),
+    UnknownTile { theme: String, name: String },
+    Empty { theme: String, list: &'static str }, // or weighted to nothing
+    NegativeWeight { theme: String, name: String },
+    BlockingDecoration { theme: String, name: String },
+    Monster { theme: String, source: Box<CreatureError> },
+}
+
+impl fmt::Display for ThemeRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            MapTheme::DungeonTheme => {
-                match tile_type {
-                    TileType::ThemeFloor => TileType::FloorSandStone0,
-                    TileType::ThemeWall => TileType::WallShoals2,
-                    TileType::ThemeExit => TileType::GatewaysLairEnter,
-                    _ => panic!("Unknown TileType")
-                }
-            },
-            MapTheme::ForestTheme => {
-                match tile_type {
-                    TileType::ThemeFloor => TileType::FloorDirt0,
-                    TileType::ThemeWall => TileType::WallTreesMangrove1,
-                    TileType::ThemeExit => TileType::GatewaysDepthsEnter,
-                    _ => panic!("Unknown TileType")
-                }
+            ThemeRegistryError::Tiles(error) => write!(f, "{}", error),
+            ThemeRegistryError::Io(source) => write!(f, "Couldn't read {}: {}", THEME_REGISTRY_PATH, source),
+            ThemeRegistryError::Parse(source) => write!(f, "Couldn't parse {}: {}", THEME_REGISTRY_PATH, source),
+            ThemeRegistryError::DefinedTwice(name) => write!(f, "Theme {} is defined twice in {}", name, THEME_REGISTRY_PATH),
+            ThemeRegistryError::UnknownTile { theme, name } => write!(
+                f, "Theme {} in {} uses an unknown tile {}", theme, THEME_REGISTRY_PATH, name
+            ),
+            ThemeRegistryError::Empty { theme, list } => write!(
+                f, "Theme {} in {} has no {} to pick from", theme, THEME_REGISTRY_PATH, list
+            ),
+            ThemeRegistryError::NegativeWeight { theme, name } => write!(
+                f, "Theme {} in {} weighs {} below zero", theme, THEME_REGISTRY_PATH, name
+            ),
+            ThemeRegistryError::BlockingDecoration { theme, name } => write!(
+                f, "Theme {} in {} decorates with {}, which blocks movement", theme, THEME_REGISTRY_PATH, name
+            ),
+            ThemeRegistryError::Monster { theme, source } => write!(
+                f, "Theme {} in {} has a monster that won't load. {}", theme, THEME_REGISTRY_PATH, source
+            ),
+        }
+    }
+}
+
+impl Error for ThemeRegistryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ThemeRegistryError::Tiles(error) => Some(*error),
+            ThemeRegistryError::Io(source) => Some(source),
+            ThemeRegistryError::Parse(source) => Some(source),
+            ThemeRegistryError::Monster { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl ThemeDefinition {
+    fn from_file(file: ThemeFile) -> Result<Self, ThemeRegistryError> {
+        // Every list a map is picked from needs something with a weight, and
+        // no weight may be negative or it skews the others
+        let lists = [
+            (&file.floors, "floors", true),
+            (&file.walls, "walls", true),
+            (&file.exits, "exits", true),
+            (&file.decorations, "decorations", file.decoration_percent > 0),
+            (&file.monsters, "monsters", true),
+        ];
+        for (list, what, picked_from) in lists {
+            if let Some((name, _)) = list.iter().find(|(_, weight)| *weight < 0) {
+                return Err(ThemeRegistryError::NegativeWeight { theme: file.name.clone(), name: name.clone() });
             }
+            if picked_from && list.iter().map(|(_, weight)| weight).sum::<i32>() <= 0 {
+                return Err(ThemeRegistryError::Empty { theme: file.name.clone(), list: what });
+            }
+        }
+        // Monsters are spawned from their creature files, so those must load too
+        for (name, _) in file.monsters.iter() {
+            StatBlock::load(name).map_err(|source| ThemeRegistryError::Monster { theme: file.name.clone(), source: Box::new(source) })?;
+        }
+        let tiles = |list: Vec<(String, i32)>| -> Result<Vec<(TileType, i32)>, ThemeRegistryError> {
+            list.into_iter()
+                .map(|(name, weight)| match TileType::named(&name) {
+                    Some(tile) => Ok((tile, weight)),
+                    None => Err(ThemeRegistryError::UnknownTile { theme: file.name.clone(), name }),
+                })
+                .collect()
+        };
+        // A decoration in the way could cut a map in two
+        let decorations = tiles(file.decorations)?;
+        if let Some((tile, _)) = decorations.iter().find(|(tile, _)| tile.definition().blocks_movement) {
+            return Err(ThemeRegistryError::BlockingDecoration { theme: file.name.clone(), name: tile.name().to_string() });
+        }
+        Ok(Self {
+            floors: tiles(file.floors)?,
+            walls: tiles(file.walls)?,
+            exits: tiles(file.exits)?,
+            decorations,
+            name: file.name,
+            decoration_percent: file.decoration_percent,
+            monsters: file.monsters,
+        })
+    }
+    fn tiles(&self, tile: ThemeTile) -> &[(TileType, i32)] {
+        match tile {
+            ThemeTile::Floor => &self.floors,
+            ThemeTile::Wall => &self.walls,
+            ThemeTile::Exit => &self.exits,
+        }
+    }
+}
+
+// Every theme, loaded once from resources/themes.ron
+pub struct ThemeRegistry {
+    definitions: Vec<ThemeDefinition>,
+    by_name: HashMap<String, MapTheme>,
+}
+
+impl ThemeRegistry {
+    pub fn new(definitions: Vec<ThemeDefinition>) -> Result<Self, ThemeRegistryError> {
+        let mut by_name = HashMap::new();
+        for (i, definition) in definitions.iter().enumerate() {
+            if by_name.insert(definition.name.clone(), MapTheme(i as u16)).is_some() {
+                return Err(ThemeRegistryError::DefinedTwice(definition.name.clone()));
+            }
+        }
+        Ok(Self { definitions, by_name })
+    }
+    // Themes as written in resources/themes.ron, checked against the tile registry
+    pub fn parse(themes: &str) -> Result<Self, ThemeRegistryError> {
+        TileRegistry::try_global().map_err(ThemeRegistryError::Tiles)?;
+        let themes: Vec<ThemeFile> = ron::from_str(themes).map_err(ThemeRegistryError::Parse)?;
+        Self::new(themes.into_iter().map(ThemeDefinition::from_file).collect::<Result<_, _>>()?)
+    }
+    pub fn load() -> Result<Self, ThemeRegistryError> {
+        Self::parse(&fs::read_to_string(THEME_REGISTRY_PATH).map_err(ThemeRegistryError::Io)?)
+    }
+    // The registry, or why it couldn't be loaded. Like the tile registry,
+    // it is checked before any map is built.
+    pub fn try_global() -> Result<&'static ThemeRegistry, &'static ThemeRegistryError> {
+        THEME_REGISTRY.get_or_init(ThemeRegistry::load).as_ref()
+    }
+    pub fn global() -> &'static ThemeRegistry {
+        match Self::try_global() {
+            Ok(registry) => registry,
+            Err(error) => panic!("{}, and maps can't be built without it", error),
         }
     }
+    pub fn find(&self, name: &str) -> Option<MapTheme> {
+        self.by_name.get(name).copied()
+    }
+    pub fn get(&self, theme: MapTheme) -> &ThemeDefinition {
+        &self.definitions[theme.0 as usize]
+    }
+    pub fn len(&self) -> usize {
+        self.definitions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.definitions.is_empty()
+    }
+    pub fn themes(&self) -> impl Iterator<Item = MapTheme> {
+        (0..self.definitions.len()).map(|i| MapTheme(i as u16))
+    }
 }
 
+// A theme's place in the theme registry. Map files name it the same way
+// they always have, as a bare identifier such as `theme: ForestTheme`.
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct MapTheme(u16);
+
+impl MapTheme {
+    pub fn named(name: &str) -> Option<Self> {
+        ThemeRegistry::try_global().ok()?.find(name)
+    }
+    pub fn definition(&self) -> &'static ThemeDefinition {
+        ThemeRegistry::global().get(*self)
+    }
+    pub fn name(&self) -> &'static str {
+        &self.definition().name
+    }
+    // Creature files that may be spawned in this theme, with their relative weights
+    pub fn monster_table(&self) -> &'static [(String, i32)] {
+        &self.definition().monsters
+    }
+    // The theme's main tile for a part of the map, which architects lay out with
+    pub fn tile_to_render(&self, tile: ThemeTile) -> TileType {
+        self.definition().tiles(tile)[0].0
+    }
+    // Rolls one of the theme's tiles for a part of the map
+    pub fn pick(&self, tile: ThemeTile, rng: &mut RandomNumberGenerator) -> TileType {
+        *pick_weighted(self.definition().tiles(tile), rng)
+    }
+    pub fn pick_decoration(&self, rng: &mut RandomNumberGenerator) -> Option<TileType> {
+        let definition = self.definition();
+        if definition.decorations.is_empty() || rng.range(0, 100) >= definition.decoration_percent {
+            return None;
+        }
+        Some(*pick_weighted(&definition.decorations, rng))
+    }
+}
+
+impl fmt::Debug for MapTheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for MapTheme {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ThemeVisitor;
+
+        impl<'de> de::Visitor<'de> for ThemeVisitor {
+            type Value = MapTheme;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a theme from {}", THEME_REGISTRY_PATH)
+            }
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<MapTheme, E> {
+                ThemeRegistry::try_global()
+                    .map_err(E::custom)?
+                    .find(name)
+                    .ok_or_else(|| E::custom(format!("no theme named {} in {}", name, THEME_REGISTRY_PATH)))
+            }
+            // Themes used to be enum variants, and RON still writes them that way
+            fn visit_enum<A: de::EnumAccess<'de>>(self, data: A) -> Result<MapTheme, A::Error> {
+                let (theme, variant) = data.variant_seed(ThemeVisitor)?;
+                de::VariantAccess::unit_variant(variant)?;
+                Ok(theme)
+            }
+        }
+
+        impl<'de> de::DeserializeSeed<'de> for ThemeVisitor {
+            type Value = MapTheme;
+
+            fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<MapTheme, D::Error> {
+                deserializer.deserialize_identifier(self)
+            }
+        }
+
+        deserializer.deserialize_enum("MapTheme", &[], ThemeVisitor)
+    }
+}
+
+impl Serialize for MapTheme {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_unit_variant("MapTheme", self.0 as u32, self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Themed {
+        theme: MapTheme,
+    }
+
+    #[test]
+    fn map_files_name_themes_with_bare_identifiers() {
+        let themed: Themed = ron::from_str("(theme: ForestTheme)").unwrap();
+        assert_eq!(themed.theme, MapTheme::named("ForestTheme").unwrap());
+        assert!(ron::from_str::<Themed>("(theme: SwampTheme)").is_err());
+    }
+
+    #[test]
+    fn picks_every_weighted_tile() {
+        let mut rng = RandomNumberGenerator::seeded(3);
+        for theme in ThemeRegistry::global().themes() {
+            for part in [ThemeTile::Floor, ThemeTile::Wall, ThemeTile::Exit] {
+                let picked: Vec<TileType> = (0..1000).map(|_| theme.pick(part, &mut rng)).collect();
+                for (tile, weight) in theme.definition().tiles(part) {
+                    assert_eq!(picked.contains(tile), *weight > 0, "{:?} {:?} {}", theme, part, tile.name());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bad_theme_files_are_errors() {
+        let theme = |floors: &str, exits: &str| {
+            format!("[(name: \"SwampTheme\", floors: {}, walls: [(\"wall_shoals_1\", 1)], exits: {}, monsters: [(\"goblin\", 1)])]", floors, exits)
+        };
+        let decorated = |decorations: &str, percent: i32| {
+            theme("[(\"floor_dirt_0\", 1)]", "[(\"chasm\", 1)]")
+                .replace("monsters:", &format!("decorations: {}, decoration_percent: {}, monsters:", decorations, percent))
+        };
+        let error = |themes: String| ThemeRegistry::parse(&themes).err().unwrap().to_string();
+        assert!(ThemeRegistry::parse(&theme("[(\"floor_dirt_0\", 1)]", "[(\"chasm\", 1)]")).is_ok());
+        assert_eq!(
+            error(theme("[(\"floor_swamp\", 1)]", "[(\"chasm\", 1)]")),
+            "Theme SwampTheme in resources/themes.ron uses an unknown tile floor_swamp"
+        );
+        assert_eq!(
+            error(theme("[(\"floor_dirt_0\", 1)]", "[]")),
+            "Theme SwampTheme in resources/themes.ron has no exits to pick from"
+        );
+        assert!(error(theme("[(\"floor_dirt_0\", 0)]", "[(\"chasm\", 1)]")).contains("no floors"));
+        assert_eq!(
+            error(theme("[(\"floor_dirt_0\", 2), (\"floor_dirt_1\", -1)]", "[(\"chasm\", 1)]")),
+            "Theme SwampTheme in resources/themes.ron weighs floor_dirt_1 below zero"
+        );
+        assert!(ThemeRegistry::parse(&decorated("[(\"rubble\", 0)]", 0)).is_ok());
+        assert!(error(decorated("[(\"rubble\", 0)]", 5)).contains("no decorations"));
+        assert!(error(decorated("[]", 5)).contains("no decorations"));
+        assert_eq!(
+            error(decorated("[(\"rubble\", 1), (\"stained_glass\", 1)]", 5)),
+            "Theme SwampTheme in resources/themes.ron decorates with stained_glass, which blocks movement"
+        );
+        assert!(error(theme("[(\"floor_dirt_0\", 1)]", "[(\"chasm\", 1)]").replace("goblin", "gobbo")).starts_with(
+            "Theme SwampTheme in resources/themes.ron has a monster that won't load. Couldn't read resources/creatures/gobbo.ron"
+        ));
+        assert!(error("[(name: \"SwampTheme\")]".to_string()).starts_with("Couldn't parse resources/themes.ron"));
+    }
+}
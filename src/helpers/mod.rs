@@ -1,15 +1,23 @@
 pub mod camera;
+pub mod cover;
+pub mod creature;
 pub mod distance;
+pub mod launcher;
 pub mod map;
 pub mod map_builder;
 pub mod range_finder;
+pub mod rng;
 pub mod tiles;
 
 pub mod prelude {
-    pub use crate::camera::*;
-    pub use crate::distance::*;
-    pub use crate::map::*;
-    pub use crate::map_builder::*;
-    pub use crate::range_finder::*;
-    pub use crate::tiles::*;
+    pub use crate::helpers::camera::*;
+    pub use crate::helpers::cover::*;
+    pub use crate::helpers::creature::*;
+    pub use crate::helpers::distance::*;
+    pub use crate::helpers::launcher::*;
+    pub use crate::helpers::map::*;
+    pub use crate::helpers::map_builder::*;
+    pub use crate::helpers::range_finder::*;
+    pub use crate::helpers::rng::*;
+    pub use crate::helpers::tiles::*;
 }
\ No newline at end of file
@@ -1,16 +1,28 @@
-use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 
 use crate::prelude::*;
 
-use super::map::Map;
+use super::creature::MovementModes;
+use super::distance::DiagonalRule;
+use super::map::{Map, STEP_DELTAS};
+
+// The cheapest known way into a point with an even or odd number of
+// diagonals taken so far, and the point and parity it was reached from
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+struct Arrival {
+    g: u32,
+    via: Option<(Point, bool)>,
+}
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct GridPoint {
     pub point: Point,
     cost: u32,
     g: u32,
-    neighbors: Vec<Point>,
     via: Option<Point>,
+    odd_diagonals: bool,
+    arrivals: [Option<Arrival>; 2],
 }
 
 impl GridPoint {
@@ -19,125 +31,113 @@ impl GridPoint {
             point: Point::new(x, y),
             cost,
             g: 0,
-            neighbors: Vec::new(),
             via: None,
+            odd_diagonals: false,
+            arrivals: [None, None],
         }
     }
+    // Total feet spent to reach this point from the anchor
+    pub fn path_cost(&self) -> u32 {
+        self.g
+    }
 }
 
 pub struct RangeFinder {}
 
 impl RangeFinder {
-    pub fn compute_grid(anchor: Point, range: u32, map: Map) -> HashMap<Point, GridPoint> {
+    // Every point a creature moving with `modes` can reach from `anchor`
+    // by spending at most `range` feet
+    pub fn compute_grid(anchor: Point, range: u32, map: &Map, modes: MovementModes) -> HashMap<Point, GridPoint> {
         let mut grid: HashMap<Point, GridPoint> = HashMap::new();
-        let mut open_set: HashSet<Point> = HashSet::new();
-        let mut closed_set: HashSet<Point> = HashSet::new();
-
-        grid.insert(anchor, GridPoint::new(anchor.x as u16, anchor.y as u16, 5));
-        open_set.insert(anchor);
-
-        while !open_set.is_empty() {
-            // get the first with the lowest cost
-            // on start this will just be the origin tile, with
-            // cost of zero.
-            let mut some_lowest_cost_grid_point: Option<&GridPoint> = None;
-            let mut lowest_cost_point: Option<Point> = None;
-            for point in &open_set {
-                let grid_point = grid.get(&point).unwrap();
-
-                if some_lowest_cost_grid_point.is_none() || some_lowest_cost_grid_point.unwrap().cost < grid_point.cost {
-                    some_lowest_cost_grid_point = Some(grid_point);
-                    lowest_cost_point = Some(grid_point.point.clone());
-                }
-            }
+        // Min-heap on the cost so far. Under the alternating rule the cost of
+        // the next diagonal depends on how many came before, so each point is
+        // searched twice, once per parity. Stale entries are skipped when popped.
+        let mut open_set: BinaryHeap<Reverse<(u32, i32, i32, bool)>> = BinaryHeap::new();
+        let rule = map.diagonal_rule;
 
-            //remove current from openSet
-            let lowest_cost_grid_point = some_lowest_cost_grid_point.unwrap();
-            let current_point = lowest_cost_grid_point.point;
-            open_set.remove(&current_point);
-            //add current to closedSet
-            closed_set.insert(current_point);
-            let current_cost = lowest_cost_grid_point.g;
-            let mut neighbors = Vec::new();
-            if current_point.x < map.dimensions.x - 1 {
-                neighbors
-                    .push(current_point + Point::new(1, 0));
-            }
-            if current_point.x > 0 {
-                neighbors
-                    .push(current_point + Point::new(-1, 0));
-            }
-            if current_point.y < map.dimensions.y - 1 {
-                neighbors
-                    .push(current_point + Point::new(0, 1));
-            }
-            if current_point.y > 0 {
-                neighbors
-                    .push(current_point + Point::new(0, -1));
-            }
-    
-            // Get all the neighbours, then iterate
-            for i in 0..neighbors.len() {
-                // Check if the neighbour has been processed
-                if !(closed_set.contains(&neighbors[i])) {
-                    if !grid.contains_key(&neighbors[i]) {
-                        let idx = map.map_idx(neighbors[i].x, neighbors[i].y);
-                        let tile = &map.tiles[idx];
-                        grid
-                            .insert(neighbors[i], GridPoint::new(neighbors[i].x as u16, neighbors[i].y as u16, tile.terrain_cost as u32));
-                    }
-                    let mut neighbor = grid.get_mut(&neighbors[i]).unwrap();
+        let anchor_tile = &map.tiles[map.map_idx(anchor.x, anchor.y)];
+        let anchor_cost = anchor_tile.movement_cost(modes).unwrap_or(anchor_tile.terrain_cost as u32);
+        let mut anchor_point = GridPoint::new(anchor.x as u16, anchor.y as u16, anchor_cost);
+        anchor_point.arrivals[0] = Some(Arrival { g: 0, via: None });
+        grid.insert(anchor, anchor_point);
+        open_set.push(Reverse((0, anchor.x, anchor.y, false)));
 
-                    // The cost of coming here from the current tile
-                    // is the total to the current tile plus
-                    // the cost of entering this tile
-                    let possible_g = current_cost + neighbor.cost;
+        while let Some(Reverse((current_cost, x, y, odd))) = open_set.pop() {
+            let current_point = Point::new(x, y);
+            if !matches!(grid[&current_point].arrivals[odd as usize], Some(arrival) if arrival.g == current_cost) {
+                continue;
+            }
+            for delta in STEP_DELTAS {
+                let neighbor_point = current_point + delta;
+                if neighbor_point == anchor || !map.can_step(current_point, delta, modes) {
+                    continue;
+                }
+                let diagonal = delta.x != 0 && delta.y != 0;
+                let neighbor_odd = odd ^ (diagonal && rule == DiagonalRule::Alternating);
+                let tile = &map.tiles[map.map_idx(neighbor_point.x, neighbor_point.y)];
+                let neighbor = grid
+                    .entry(neighbor_point)
+                    .or_insert_with(|| GridPoint::new(neighbor_point.x as u16, neighbor_point.y as u16, tile.movement_cost(modes).unwrap_or_default()));
 
-                    // If the cost is already greater than the range
-                    // do not consider this tile anymore
-                    if possible_g > range {
-                        continue;
-                    }
+                // The cost of coming here from the current tile
+                // is the total to the current tile plus
+                // the cost of entering this tile
+                let possible_g = current_cost + rule.step_cost(neighbor.cost, diagonal, odd);
 
-                    // If not yet in consideration, all to open set
-                    // If already in consideration, and the cost from
-                    // current is higher than current total, skip
-                    if !(open_set.contains(&neighbor.point)) {
-                        open_set.insert(neighbor.point);
-                    } else if possible_g >= neighbor.g {
+                // Out of range, or already reached at least as cheaply
+                if possible_g > range {
+                    continue;
+                }
+                if let Some(arrival) = neighbor.arrivals[neighbor_odd as usize] {
+                    if possible_g >= arrival.g {
                         continue;
                     }
-
-                    // Otherwise update current cost, and choose current
-                    // as preferred
-                    neighbor.g = possible_g;
-                    neighbor.via = lowest_cost_point;
                 }
+                neighbor.arrivals[neighbor_odd as usize] = Some(Arrival { g: possible_g, via: Some((current_point, odd)) });
+                open_set.push(Reverse((possible_g, neighbor_point.x, neighbor_point.y, neighbor_odd)));
             }
         }
+        // Settle each point on its cheapest arrival, preferring an even
+        // number of diagonals on ties since the next diagonal is cheaper
+        for grid_point in grid.values_mut() {
+            let best = [false, true]
+                .into_iter()
+                .filter_map(|odd| grid_point.arrivals[odd as usize].map(|arrival| (arrival.g, odd, arrival.via)))
+                .min_by_key(|(g, odd, _)| (*g, *odd));
+            if let Some((g, odd, via)) = best {
+                grid_point.g = g;
+                grid_point.odd_diagonals = odd;
+                grid_point.via = via.map(|(point, _)| point);
+            }
+        }
+        // Drop tiles that were looked at but never reached within range
+        grid.retain(|point, grid_point| *point == anchor || grid_point.via.is_some());
         grid
     }
 
-    pub fn get_grid(grid: HashMap<Point, GridPoint>) -> Vec<Point> {
-        grid.clone()
-            .iter()
-            .map(|(_, x)| x )
+    pub fn get_grid(grid: &HashMap<Point, GridPoint>) -> Vec<Point> {
+        grid
+            .values()
             .filter(|x| x.via.is_some())
             .map(|x| x.point)
             .collect()
     }
 
-    pub fn get_path_to(grid: HashMap<Point, GridPoint>, point: Point) -> Vec<Point> {
+    pub fn get_path_to(grid: &HashMap<Point, GridPoint>, point: Point) -> Vec<Point> {
         let mut path = Vec::new();
         match grid.get(&point) {
             None => path,
             Some(mut grid_point) => {
-                while let Some(via) = grid_point.via {
+                // Follow the arrivals rather than each point's own best via,
+                // so the diagonal parity stays consistent along the path
+                let mut odd = grid_point.odd_diagonals;
+                while let Some((via, via_odd)) = grid_point.arrivals[odd as usize].and_then(|arrival| arrival.via) {
                     path.push(via);
                     match grid.get(&via) {
                         None => break,
                         Some(next) => {
                             grid_point = next;
+                            odd = via_odd;
                         }
                     };
                 }
@@ -145,4 +145,213 @@ impl RangeFinder {
             }
         }
     }
+
+    // Feet spent on each step of walking `steps` from `start`, in order
+    pub fn get_step_costs(map: &Map, start: Point, steps: &[Point], modes: MovementModes) -> Vec<u32> {
+        let mut costs = Vec::with_capacity(steps.len());
+        let mut previous = start;
+        let mut odd = false;
+        for step in steps {
+            let delta = *step - previous;
+            let diagonal = delta.x != 0 && delta.y != 0;
+            let cost = map.tiles[map.map_idx(step.x, step.y)].movement_cost(modes).unwrap_or_default();
+            costs.push(map.diagonal_rule.step_cost(cost, diagonal, odd));
+            odd ^= diagonal && map.diagonal_rule == DiagonalRule::Alternating;
+            previous = *step;
+        }
+        costs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::helpers::map_builder::themes::MapTheme;
+    use crate::helpers::tiles::{Terrain, Tile};
+
+    const FLOOR_COST: u32 = 5;
+
+    // A map of the given size, walls wherever `walls` is true
+    fn build_map(width: usize, height: usize, walls: &[bool]) -> Map {
+        let mut map = Map::new(width as u32, height as u32, MapTheme::named("DungeonTheme").unwrap());
+        for (idx, wall) in walls.iter().enumerate() {
+            if *wall {
+                map.tiles[idx] = Tile::named("wall_shoals_2").unwrap();
+            }
+        }
+        map
+    }
+
+    // Cheapest cost to every point by relaxing every step until nothing
+    // improves, tracking diagonal parity the same way compute_grid does
+    fn brute_force(anchor: Point, map: &Map, modes: MovementModes) -> HashMap<Point, u32> {
+        let mut best: HashMap<(Point, bool), u32> = HashMap::new();
+        best.insert((anchor, false), 0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for ((point, odd), g) in best.clone() {
+                for delta in STEP_DELTAS {
+                    if !map.can_step(point, delta, modes) {
+                        continue;
+                    }
+                    let next = point + delta;
+                    let diagonal = delta.x != 0 && delta.y != 0;
+                    let next_odd = odd ^ (diagonal && map.diagonal_rule == DiagonalRule::Alternating);
+                    let cost = map.tiles[map.map_idx(next.x, next.y)].movement_cost(modes).unwrap();
+                    let next_g = g + map.diagonal_rule.step_cost(cost, diagonal, odd);
+                    if !matches!(best.get(&(next, next_odd)), Some(old) if *old <= next_g) {
+                        best.insert((next, next_odd), next_g);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        let mut result: HashMap<Point, u32> = HashMap::new();
+        for ((point, _), g) in best {
+            let entry = result.entry(point).or_insert(g);
+            *entry = (*entry).min(g);
+        }
+        result
+    }
+
+    fn arb_map() -> impl Strategy<Value = (usize, usize, Vec<bool>, Vec<u8>, usize)> {
+        (2..12usize, 2..12usize).prop_flat_map(|(width, height)| {
+            (
+                Just(width),
+                Just(height),
+                prop::collection::vec(prop::bool::weighted(0.3), width * height),
+                prop::collection::vec(1..=15u8, width * height),
+                0..width * height,
+            )
+        })
+    }
+
+    fn arb_rule() -> impl Strategy<Value = DiagonalRule> {
+        prop_oneof![
+            Just(DiagonalRule::Chebyshev),
+            Just(DiagonalRule::Alternating),
+            Just(DiagonalRule::Euclidean),
+        ]
+    }
+
+    #[test]
+    fn walks_through_curtains_but_not_chasms_windows_or_portcullises() {
+        let mut map = build_map(5, 1, &[false; 5]);
+        map.tiles[1] = Tile::named("curtain").unwrap();
+        let grid = RangeFinder::compute_grid(Point::new(0, 0), 100, &map, MovementModes::default());
+        assert!(grid.contains_key(&Point::new(4, 0)));
+        for blocker in ["chasm", "window", "portcullis"] {
+            map.tiles[2] = Tile::named(blocker).unwrap();
+            let grid = RangeFinder::compute_grid(Point::new(0, 0), 100, &map, MovementModes::default());
+            assert!(grid.contains_key(&Point::new(1, 0)));
+            assert!(!grid.contains_key(&Point::new(3, 0)), "walked past {:?}", blocker);
+        }
+    }
+
+    #[test]
+    fn movement_modes_change_what_tiles_cost() {
+        // Mud, water, a low wall and a chasm in a row
+        let mut map = build_map(5, 1, &[false; 5]);
+        for (idx, name) in ["mud", "water_shallow", "wall_stone_low", "chasm"].into_iter().enumerate() {
+            map.tiles[idx + 1] = Tile::named(name).unwrap();
+        }
+        let cost_to = |modes: MovementModes, x: i32| {
+            RangeFinder::compute_grid(Point::new(0, 0), 100, &map, modes).get(&Point::new(x, 0)).map(GridPoint::path_cost)
+        };
+        let walker = MovementModes::default();
+        assert_eq!(cost_to(walker, 2), Some(20));
+        assert_eq!(cost_to(walker, 3), None);
+        let swimmer = MovementModes { swim: true, ..walker };
+        assert_eq!(cost_to(swimmer, 2), Some(15));
+        let climber = MovementModes { climb: true, ..walker };
+        assert_eq!(cost_to(climber, 3), Some(30));
+        assert_eq!(cost_to(climber, 4), None);
+        let burrower = MovementModes { burrow: true, ..walker };
+        assert_eq!(cost_to(burrower, 1), Some(5));
+        let flyer = MovementModes { fly: true, ..walker };
+        assert_eq!(cost_to(flyer, 4), Some(20));
+    }
+
+    fn arb_modes() -> impl Strategy<Value = MovementModes> {
+        (any::<bool>(), any::<bool>(), any::<bool>(), any::<bool>())
+            .prop_map(|(swim, climb, fly, burrow)| MovementModes { swim, climb, fly, burrow })
+    }
+
+    proptest! {
+        #[test]
+        fn matches_dijkstra_map_on_uniform_floors((width, height, walls, _, anchor_idx) in arb_map(), range in 0..200u32) {
+            // Every step costs the same under Chebyshev, just like DijkstraMap's exits
+            let mut map = build_map(width, height, &walls);
+            map.diagonal_rule = DiagonalRule::Chebyshev;
+            let anchor = map.index_to_point2d(anchor_idx);
+            let grid = RangeFinder::compute_grid(anchor, range, &map, MovementModes::default());
+            let dijkstra_map = DijkstraMap::new(width, height, &[anchor_idx], &map, 1024.0);
+            for (idx, depth) in dijkstra_map.map.iter().enumerate() {
+                let point = map.index_to_point2d(idx);
+                if point == anchor {
+                    continue;
+                }
+                let in_range = *depth < f32::MAX && (*depth as u32) * FLOOR_COST <= range;
+                match grid.get(&point) {
+                    Some(grid_point) => {
+                        prop_assert!(in_range, "{:?} reached but should not be", point);
+                        prop_assert_eq!(grid_point.path_cost(), *depth as u32 * FLOOR_COST);
+                    }
+                    None => prop_assert!(!in_range, "{:?} should be reachable", point),
+                }
+            }
+        }
+
+        #[test]
+        fn paths_are_minimal_with_mixed_costs(
+            (width, height, walls, costs, anchor_idx) in arb_map(),
+            range in 0..200u32,
+            rule in arb_rule(),
+            terrains in prop::collection::vec(0..6u8, 144),
+            modes in arb_modes(),
+        ) {
+            let mut map = build_map(width, height, &walls);
+            map.diagonal_rule = rule;
+            for ((tile, cost), terrain) in map.tiles.iter_mut().zip(costs).zip(terrains) {
+                tile.terrain_cost = cost;
+                if tile.terrain == Terrain::Solid {
+                    continue;
+                }
+                (tile.terrain, tile.blocks_movement) = match terrain {
+                    0 => (Terrain::Water, false),
+                    1 => (Terrain::Obstacle, true),
+                    2 => (Terrain::Chasm, true),
+                    _ => (Terrain::Ground, false),
+                };
+            }
+            let anchor = map.index_to_point2d(anchor_idx);
+            let grid = RangeFinder::compute_grid(anchor, range, &map, modes);
+            let expected = brute_force(anchor, &map, modes);
+            for (point, g) in expected.iter() {
+                if *point == anchor {
+                    continue;
+                }
+                match grid.get(point) {
+                    Some(grid_point) => prop_assert_eq!(grid_point.path_cost(), *g),
+                    None => prop_assert!(*g > range, "{:?} costs {} and should be reachable", point, g),
+                }
+            }
+            for point in RangeFinder::get_grid(&grid) {
+                prop_assert!(expected.contains_key(&point));
+                // Walking the path forwards costs exactly what was recorded
+                let mut path = RangeFinder::get_path_to(&grid, point);
+                prop_assert_eq!(*path.last().unwrap(), anchor);
+                path.reverse();
+                path.push(point);
+                for step in path.windows(2) {
+                    prop_assert!(map.can_step(step[0], step[1] - step[0], modes));
+                }
+                let walked: u32 = RangeFinder::get_step_costs(&map, anchor, &path[1..], modes).iter().sum();
+                prop_assert_eq!(walked, grid[&point].path_cost());
+            }
+        }
+    }
 }
@@ -0,0 +1,54 @@
+use crate::prelude::*;
+
+// Shared random number generator for everything that rolls dice during an encounter
+#[derive(Resource)]
+pub struct GameRng(pub RandomNumberGenerator);
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self(RandomNumberGenerator::new())
+    }
+}
+
+impl GameRng {
+    pub fn seeded(seed: u64) -> Self {
+        Self(RandomNumberGenerator::seeded(seed))
+    }
+}
+
+// The seed a session's maps and dice come from, so it can be played again
+#[derive(Resource, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Seed(pub u64);
+
+// Rolls against the weights of a table of (entry, weight)
+pub fn pick_weighted<'a, T>(table: &'a [(T, i32)], rng: &mut RandomNumberGenerator) -> &'a T {
+    let total: i32 = table.iter().map(|(_, weight)| weight).sum();
+    let mut roll = rng.range(0, total);
+    for (entry, weight) in table {
+        if roll < *weight {
+            return entry;
+        }
+        roll -= weight;
+    }
+    &table[0].0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn pick_weighted_skips_zero_weights() {
+        let table = [("goblin", 0), ("wolf", 3), ("skeleton", 0), ("giant_rat", 1)];
+        let mut rng = RandomNumberGenerator::seeded(7);
+        let mut picked: HashMap<&str, i32> = HashMap::new();
+        for _ in 0..1000 {
+            *picked.entry(*pick_weighted(&table, &mut rng)).or_insert(0) += 1;
+        }
+        assert!(!picked.contains_key("goblin"));
+        assert!(!picked.contains_key("skeleton"));
+        assert!(picked["wolf"] > picked["giant_rat"]);
+    }
+}
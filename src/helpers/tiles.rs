@@ -1,36 +1,181 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::sync::OnceLock;
+
+use ron::de::from_reader;
+use ron::error::SpannedError;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::prelude::{*, illumination::IlluminationLevel};
 
+use super::cover::Cover;
+use super::creature::MovementModes;
+use super::distance::TILE_SIZE_IN_FEET;
 use super::map::SeenLevel;
 
+const TILE_REGISTRY_PATH: &str = "resources/tiles.ron";
+
+static TILE_REGISTRY: OnceLock<Result<TileRegistry, TileRegistryError>> = OnceLock::new();
+
+// A tile's place in the tile registry
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
-pub enum TileType {
-    ThemeFloor, // should map to something else
-    ThemeWall, // Should map to somethine else
-    ThemeExit, // should map to something else
-    FloorSandStone0, //51
-    WallShoals2, //233
-    GatewaysLairEnter,// 144
-    FloorDirt0, // 35
-    WallTreesMangrove1, // 176
-    GatewaysDepthsEnter, // 137
-}
+pub struct TileType(u16);
 
 impl TileType {
-    pub fn to_texture_index(&self) -> u8 {
+    // Looks a tile up by its name in the registry
+    pub fn named(name: &str) -> Option<Self> {
+        TileRegistry::try_global().ok()?.find(name)
+    }
+    pub fn definition(&self) -> &'static TileDefinition {
+        TileRegistry::global().get(*self)
+    }
+    pub fn name(&self) -> &'static str {
+        &self.definition().name
+    }
+    pub fn to_texture_index(&self) -> u32 {
+        self.definition().texture_index
+    }
+}
+
+// Saved by name, so a save still loads after tiles are added to the registry
+impl Serialize for TileType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for TileType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        TileRegistry::try_global()
+            .map_err(de::Error::custom)?
+            .find(&name)
+            .ok_or_else(|| de::Error::custom(format!("no tile named {} in {}", name, TILE_REGISTRY_PATH)))
+    }
+}
+
+fn default_terrain_cost() -> u8 {
+    TILE_SIZE_IN_FEET as u8
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TileDefinition {
+    pub name: String,
+    pub texture_index: u32, // in ground.png
+    #[serde(default)]
+    pub blocks_sight: bool,
+    #[serde(default)]
+    pub blocks_movement: bool,
+    #[serde(default)]
+    pub blocks_projectiles: bool,
+    #[serde(default)]
+    pub blocks_light: bool,
+    #[serde(default)]
+    pub cover: Cover,
+    #[serde(default)]
+    pub terrain: Terrain,
+    #[serde(default = "default_terrain_cost")]
+    pub terrain_cost: u8, // feet to walk in
+    #[serde(default)]
+    pub light_filter: LightFilter,
+}
+
+// Why resources/tiles.ron couldn't be loaded
+#[derive(Debug)]
+pub enum TileRegistryError {
+    Io(io::Error),
+    Parse(SpannedError),
+    DefinedTwice(String),
+}
+
+impl fmt::Display for TileRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::FloorSandStone0 => 51,
-            Self::WallShoals2 => 233,
-            Self::GatewaysLairEnter => 144,
-            Self::FloorDirt0 => 35,
-            Self::WallTreesMangrove1 => 176,
-            Self::GatewaysDepthsEnter => 137,
-            _ => panic!("not mapped")
+            TileRegistryError::Io(source) => write!(f, "Couldn't read {}: {}", TILE_REGISTRY_PATH, source),
+            TileRegistryError::Parse(source) => write!(f, "Couldn't parse {}: {}", TILE_REGISTRY_PATH, source),
+            TileRegistryError::DefinedTwice(name) => write!(f, "Tile {} is defined twice in {}", name, TILE_REGISTRY_PATH),
+        }
+    }
+}
+
+impl Error for TileRegistryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TileRegistryError::Io(source) => Some(source),
+            TileRegistryError::Parse(source) => Some(source),
+            TileRegistryError::DefinedTwice(_) => None,
+        }
+    }
+}
+
+// Every tile definition, loaded once from resources/tiles.ron
+pub struct TileRegistry {
+    definitions: Vec<TileDefinition>,
+    by_name: HashMap<String, TileType>,
+}
+
+impl TileRegistry {
+    pub fn new(definitions: Vec<TileDefinition>) -> Result<Self, TileRegistryError> {
+        let mut by_name = HashMap::new();
+        for (i, definition) in definitions.iter().enumerate() {
+            if by_name.insert(definition.name.clone(), TileType(i as u16)).is_some() {
+                return Err(TileRegistryError::DefinedTwice(definition.name.clone()));
+            }
         }
+        Ok(Self { definitions, by_name })
+    }
+    pub fn load() -> Result<Self, TileRegistryError> {
+        let file = File::open(TILE_REGISTRY_PATH).map_err(TileRegistryError::Io)?;
+        Self::new(from_reader(file).map_err(TileRegistryError::Parse)?)
+    }
+    // The registry, or why it couldn't be loaded. Maps are only built once
+    // this has succeeded, which is what lets global() take it for granted.
+    pub fn try_global() -> Result<&'static TileRegistry, &'static TileRegistryError> {
+        TILE_REGISTRY.get_or_init(TileRegistry::load).as_ref()
+    }
+    pub fn global() -> &'static TileRegistry {
+        match Self::try_global() {
+            Ok(registry) => registry,
+            Err(error) => panic!("{}, and maps can't be built without it", error),
+        }
+    }
+    pub fn find(&self, name: &str) -> Option<TileType> {
+        self.by_name.get(name).copied()
+    }
+    pub fn get(&self, tile_type: TileType) -> &TileDefinition {
+        &self.definitions[tile_type.0 as usize]
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (TileType, &TileDefinition)> {
+        self.definitions.iter().enumerate().map(|(i, definition)| (TileType(i as u16), definition))
     }
 }
 
+// What a tile is made of, which decides what it costs to cross by each
+// way of moving. How hard the ground is to walk is set by terrain_cost.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Hash)]
+pub enum Terrain {
+    #[default]
+    Ground,
+    Water,
+    Obstacle, // can be climbed over
+    Chasm,
+    Solid,
+}
+
+// What a tile does to the light that reaches it. Tiles that stop light
+// outright have blocks_light instead.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq, Hash)]
+pub enum LightFilter {
+    #[default]
+    Clear,
+    Dims, // fog and smoke, a step less light for each tile of it crossed
+    MagicalDarkness, // no light but magical light gets in
+}
+
 #[derive(Component)]
 pub struct TileIsOpaque;
 
@@ -40,8 +185,14 @@ pub struct TerrainCost(u8);
 #[derive(Clone, PartialEq, Eq)]
 pub struct Tile {
     pub tile_type: TileType,
-    pub is_opaque: bool,
+    pub blocks_sight: bool,
+    pub blocks_movement: bool,
+    pub blocks_projectiles: bool,
+    pub blocks_light: bool,
+    pub cover: Cover,
+    pub terrain: Terrain,
     pub terrain_cost: u8,
+    pub light_filter: LightFilter,
     pub illuminated_by: HashMap<Entity, IlluminationLevel>,
     pub illumination_level: IlluminationLevel,
     pub seen_by: HashMap<Entity, SeenLevel>,
@@ -50,43 +201,83 @@ pub struct Tile {
 
 impl Tile {
     pub fn new(tile_type: TileType) -> Self {
-        match tile_type {
-            TileType::FloorSandStone0 | TileType::FloorDirt0 => Self {
-                tile_type,
-                is_opaque: false,
-                terrain_cost: 5,
-                illuminated_by: HashMap::new(),
-                illumination_level: IlluminationLevel::None,
-                seen_by: HashMap::new(),
-                seen_level: SeenLevel::None,
-            },
-            TileType::WallShoals2 | TileType::WallTreesMangrove1 => Self {
-                tile_type,
-                is_opaque: true,
-                terrain_cost: 0,
-                illuminated_by: HashMap::new(),
-                illumination_level: IlluminationLevel::None,
-                seen_by: HashMap::new(),
-                seen_level: SeenLevel::None,
-            },
-            TileType::GatewaysLairEnter | TileType::GatewaysDepthsEnter => Self {
-                tile_type,
-                is_opaque: true,
-                terrain_cost: 0,
-                illuminated_by: HashMap::new(),
-                illumination_level: IlluminationLevel::None,
-                seen_by: HashMap::new(),
-                seen_level: SeenLevel::None,
-            },
-            _ => panic!("Unknown TileType")
+        let definition = tile_type.definition();
+        Self {
+            tile_type,
+            blocks_sight: definition.blocks_sight,
+            blocks_movement: definition.blocks_movement,
+            blocks_projectiles: definition.blocks_projectiles,
+            blocks_light: definition.blocks_light,
+            cover: definition.cover,
+            terrain: definition.terrain,
+            terrain_cost: definition.terrain_cost,
+            light_filter: definition.light_filter,
+            illuminated_by: HashMap::new(),
+            illumination_level: IlluminationLevel::None,
+            seen_by: HashMap::new(),
+            seen_level: SeenLevel::None,
+        }
+    }
+    pub fn named(name: &str) -> Option<Self> {
+        TileType::named(name).map(Self::new)
+    }
+    // Feet to move into this tile for a creature with the given movement
+    // modes, None when it can't. Flyers cross anything that isn't solid at
+    // the normal rate, climbers get over obstacles, swimmers aren't slowed by
+    // water and burrowers tunnel under difficult ground.
+    pub fn movement_cost(&self, modes: MovementModes) -> Option<u32> {
+        let normal = TILE_SIZE_IN_FEET as u32;
+        match self.terrain {
+            Terrain::Solid => None,
+            _ if modes.fly => Some(normal),
+            Terrain::Obstacle if modes.climb => Some(self.terrain_cost as u32),
+            _ if self.blocks_movement => None,
+            Terrain::Water if modes.swim => Some(normal),
+            Terrain::Ground if modes.burrow => Some(normal),
+            _ => Some(self.terrain_cost as u32),
         }
     }
     pub fn insert_components(&self, mut commands: Commands, entity: Entity) {
-        if self.is_opaque {
+        if self.blocks_sight {
             commands.entity(entity).insert(TileIsOpaque);
         }
         if self.terrain_cost > 0 {
             commands.entity(entity).insert(TerrainCost(self.terrain_cost));
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_registered_tile_is_in_the_atlas() {
+        // ground.png is 16 by 16 tiles
+        for (tile_type, definition) in TileRegistry::global().iter() {
+            assert!(definition.texture_index < 256, "{} is off the atlas", definition.name);
+            assert_eq!(TileType::named(&definition.name), Some(tile_type));
+        }
+        assert_eq!(TileType::named("flor_sandstone_0"), None);
+    }
+
+    #[test]
+    fn left_out_properties_make_an_open_floor() {
+        let definitions: Vec<TileDefinition> = ron::from_str("[(name: \"grass\", texture_index: 64)]").unwrap();
+        let registry = TileRegistry::new(definitions).unwrap();
+        let grass = registry.get(registry.find("grass").unwrap());
+        assert!(!grass.blocks_sight && !grass.blocks_movement && !grass.blocks_projectiles && !grass.blocks_light);
+        assert_eq!(grass.cover, Cover::None);
+        assert_eq!(grass.terrain, Terrain::Ground);
+        assert_eq!(grass.terrain_cost, 5);
+        assert_eq!(grass.light_filter, LightFilter::Clear);
+    }
+
+    #[test]
+    fn tiles_defined_twice_are_an_error() {
+        let definitions: Vec<TileDefinition> =
+            ron::from_str("[(name: \"grass\", texture_index: 64), (name: \"grass\", texture_index: 65)]").unwrap();
+        let error = TileRegistry::new(definitions).err().unwrap();
+        assert_eq!(error.to_string(), "Tile grass is defined twice in resources/tiles.ron");
+    }
+}
@@ -0,0 +1,13 @@
+pub mod helpers;
+pub mod systems;
+
+pub mod prelude {
+    pub use bevy::prelude::*;
+    pub use bevy_ecs_tilemap::prelude::*;
+    pub use bracket_pathfinding::prelude::*;
+    pub use bracket_random::prelude::*;
+    pub use crate::helpers::*;
+    pub use crate::systems::*;
+    // Bevy and bracket both have a Rect, and the map's rooms are bracket's
+    pub use bracket_pathfinding::prelude::Rect;
+}
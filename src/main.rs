@@ -1,45 +1,74 @@
-pub mod helpers;
-pub mod systems;
-
-mod prelude {
-    pub use bevy::prelude::*;
-    pub use bevy_ecs_tilemap::prelude::*;
-    pub use bracket_geometry::*;
-    pub use bracket_pathfinding::prelude::*;
-    pub use bracket_random::prelude::*;
-    pub use crate::helpers::*;
-    pub use crate::systems::*;
-}
+use adventure_encounters::{helpers, systems};
 
-use prelude::{
+use adventure_encounters::prelude::{
     *,
     map::ObjectsMapLayer,
-    map_builder::{MapBuilder, themes::MapTheme, MapArchitect, rooms::RoomsArchitect, custom::CustomFileBuilder},
-    illumination::{ProvidesIllumination, illumination_system},
-    tiles::TileType, field_of_view::FieldOfView,
+    map_builder::{MapBuilder, custom::MapLoadError, themes::ThemeTile},
+    launcher::{HeadlessOptions, LaunchOptions, MapSource, UsageError},
+    simulation::{Simulation, SimulationOutcome},
+    illumination::illumination_system,
+    field_of_view::fog_color,
+    save::{Restored, SavePlugin},
+    turn::TurnPlugin,
+    movement::MovementPlugin,
+    camera::CursorMapPoint,
+    rng::{GameRng, Seed},
+    creature::StatBlock,
+    spawner,
+    clock::ClockPlugin,
+    combat::CombatPlugin,
+    error_screen::MapLoadFailed,
+    encounter::{EncounterEntity, EncounterLoadedEvent, EncounterMap, EncounterPlugin, EncounterSet},
 };
 
-fn startup(
+fn startup(mut commands: Commands, asset_server: Res<AssetServer>, seed: Res<Seed>) {
+    commands.spawn(Camera2dBundle::default());
+
+    // The seed goes in a corner so a session can be reported and played again
+    commands.spawn(
+        TextBundle::from_section(
+            format!("Seed {}", seed.0),
+            TextStyle { font: asset_server.load("fonts/DejaVuSansMono.ttf"), font_size: 16.0, color: Color::WHITE },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect { right: Val::Px(8.0), bottom: Val::Px(8.0), ..default() },
+            ..default()
+        }),
+    );
+}
+
+// Lays out the tilemap layers, fog of war, creatures and lights for the map
+// in MapBuilder, each time a new one is loaded
+#[allow(clippy::type_complexity)]
+fn spawn_encounter(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     #[cfg(all(not(feature = "atlas"), feature = "render"))] array_texture_loader: Res<
         ArrayTextureLoader,
     >,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    map_builder: Res<MapBuilder>,
+    mut rng: ResMut<GameRng>,
+    restored: Query<(Entity, &map::MapPoint, &Restored, Option<&StatBlock>, Option<&Monster>)>,
 ) {
-    commands.spawn(Camera2dBundle::default());
-
-    let mut rng = RandomNumberGenerator::new();
-
-    // Choose how to build the map
-    // This is random
-    // let map_builder = MapBuilder::new_random(80, 50, &mut rng);
-    // These three lines are for specific arhictect and theme
-    // let architect: Box<dyn MapArchitect> = Box::new(RoomsArchitect {});
-    // let theme = MapTheme::DungeonTheme;
-    // let map_builder = MapBuilder::new(architect, theme, 80, 50, &mut rng);
-    // This loads a map
-    let map_builder = CustomFileBuilder::create_map_builder("campfire".to_string());
+    // Creatures are rolled before anything is laid out, so a creature file
+    // that won't load shows the error screen instead of half an encounter.
+    // A loaded save has spawned its creatures already.
+    let creatures = if restored.is_empty() {
+        let rolled = StatBlock::load("hero")
+            .and_then(|hero| Ok((hero, spawner::roll_monsters(&map_builder, &mut rng.0)?)));
+        match rolled {
+            Ok(creatures) => Some(creatures),
+            Err(error) => {
+                commands.remove_resource::<MapBuilder>();
+                commands.insert_resource(MapLoadFailed(MapLoadError::Creature(error).to_string()));
+                return;
+            }
+        }
+    } else {
+        None
+    };
 
     let texture_handle: Handle<Image> = asset_server.load("ground.png");
 
@@ -64,7 +93,7 @@ fn startup(
 
     // Spawn the elements of the tilemap.
     fill_tilemap(
-        TileTextureIndex(map_builder.theme.tile_to_render(TileType::ThemeFloor).to_texture_index() as u32),
+        TileTextureIndex(map_builder.theme.tile_to_render(ThemeTile::Floor).to_texture_index()),
         map_size,
         TilemapId(tilemap_entity),
         &mut commands,
@@ -80,7 +109,7 @@ fn startup(
         tile_size,
         transform: get_tilemap_center_transform(&map_size, &grid_size, &map_type, 0.0),
         ..Default::default()
-    });
+    }).insert(EncounterEntity);
 
     // Layer 2
     let tilemap_entity = commands.spawn_empty().id();
@@ -96,7 +125,7 @@ fn startup(
                 .spawn(TileBundle {
                     position: tile_pos,
                     tilemap_id: TilemapId(tilemap_entity),
-                    texture_index: TileTextureIndex(tile.tile_type.to_texture_index() as u32),
+                    texture_index: TileTextureIndex(tile.tile_type.to_texture_index()),
                     ..Default::default()
                 })
                 .id();
@@ -113,7 +142,38 @@ fn startup(
         tile_size,
         transform: get_tilemap_center_transform(&map_size, &grid_size, &map_type, 1.0),
         ..Default::default()
-    }).insert(ObjectsMapLayer);
+    }).insert(ObjectsMapLayer).insert(EncounterEntity);
+
+    // Layer movement range, every tile hidden until a unit's reachable tiles are shown
+    let highlight_handle: Handle<Image> = asset_server.load("highlight.png");
+    let tilemap_entity = commands.spawn_empty().id();
+    let mut tile_storage = TileStorage::empty(map_size);
+
+    for x in 0..map_size.x {
+        for y in 0..map_size.y {
+            let tile_pos = TilePos { x, y };
+            let tile_entity = commands
+                .spawn(TileBundle {
+                    position: tile_pos,
+                    tilemap_id: TilemapId(tilemap_entity),
+                    texture_index: TileTextureIndex(0),
+                    visible: TileVisible(false),
+                    ..Default::default()
+                })
+                .id();
+            tile_storage.set(&tile_pos, tile_entity);
+        }
+    }
+    commands.entity(tilemap_entity).insert(TilemapBundle {
+        grid_size,
+        map_type,
+        size: map_size,
+        storage: tile_storage,
+        texture: TilemapTexture::Single(highlight_handle),
+        tile_size,
+        transform: get_tilemap_center_transform(&map_size, &grid_size, &map_type, 1.5),
+        ..Default::default()
+    }).insert(helpers::map::MovementRangeMapLayer).insert(EncounterEntity);
 
     // Layer fog of war
     let tilemap_entity = commands.spawn_empty().id();
@@ -125,15 +185,17 @@ fn startup(
     // white and 95% opacity shows dim
     // black and 95% opacity shows dim much like above
     // gray and 95% opacity shows dim much like above
-    for x in 0..map_size.x {
-        for y in 0..map_size.y {
-            let tile_pos = TilePos { x, y };
+    // A restored encounter starts with the fog lifted wherever it was
+    for x in 0..map_builder.map.dimensions.x {
+        for y in 0..map_builder.map.dimensions.y {
+            let tile = &map_builder.map.tiles[map_builder.map.map_idx(x, y)];
+            let tile_pos = map_builder.map.to_bevy_ecs_tilemap(x, y);
             let tile_entity = commands
                 .spawn(TileBundle {
                     position: tile_pos,
                     tilemap_id: TilemapId(tilemap_entity),
                     texture_index: TileTextureIndex(255),
-                    color: TileColor(Color::rgba(0.0, 0.0, 0.0, 1.0)),
+                    color: TileColor(fog_color(tile.seen_level)),
                     visible: TileVisible(true),
                     ..Default::default()
                 })
@@ -150,53 +212,64 @@ fn startup(
         tile_size,
         transform: get_tilemap_center_transform(&map_size, &grid_size, &map_type, 3.0),
         ..Default::default()
-    }).insert(helpers::map::FogOfWarMapLayer);
+    }).insert(helpers::map::FogOfWarMapLayer).insert(EncounterEntity);
 
     // Load player sprite
     let texture_handle: Handle<Image> = asset_server.load("monsters.png");
     let texture_atlas =
         TextureAtlas::from_grid(texture_handle, Vec2::new(32.0, 32.0), 16, 16, None, None);
     let texture_atlas_handle = texture_atlases.add(texture_atlas);
-    let mut transform = get_tilemap_center_transform(&map_size, &grid_size, &map_type, 2.0);
-    let player_start = map_builder.player_start;
-    let in_b_e_t = map_builder.map.to_bevy_ecs_tilemap(player_start.x, player_start.y);
-    transform.translation.x += (in_b_e_t.x * 32) as f32;
-    transform.translation.y += (in_b_e_t.y * 32) as f32;
-    commands.spawn((
-        SpriteSheetBundle {
-            sprite: TextureAtlasSprite { index: 38, ..Default::default() },
-            texture_atlas: texture_atlas_handle,
-            transform,
-            ..default()
-        },
-    ))
-    // .insert(ProvidesIllumination::new(30, 60, None))
-    .insert(FieldOfView::new(60, Some(0), Some(0)))
-    .insert(map::MapPoint::new(player_start))
-    .insert(Player);
-
     let texture_handle: Handle<Image> = asset_server.load("ground.png");
     let texture_atlas =
         TextureAtlas::from_grid(texture_handle, Vec2::new(32.0, 32.0), 16, 16, None, None);
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
-    for (point, c) in map_builder.entity_spawns.clone() {
-        let mut transform = get_tilemap_center_transform(&map_size, &grid_size, &map_type, 3.0);
-        let in_b_e_t = map_builder.map.to_bevy_ecs_tilemap(point.x, point.y);
+    let lights_atlas_handle = texture_atlases.add(texture_atlas);
+    // A loaded save has spawned its creatures and lights already, and they
+    // only need drawing
+    if let Some((hero, monsters)) = creatures {
+        let mut transform = get_tilemap_center_transform(&map_size, &grid_size, &map_type, 2.0);
+        let player_start = map_builder.player_start;
+        let in_b_e_t = map_builder.map.to_bevy_ecs_tilemap(player_start.x, player_start.y);
         transform.translation.x += (in_b_e_t.x * 32) as f32;
         transform.translation.y += (in_b_e_t.y * 32) as f32;
         commands.spawn((
             SpriteSheetBundle {
-                sprite: TextureAtlasSprite { index: 135, ..Default::default() },
+                sprite: TextureAtlasSprite { index: hero.sprite_index, ..Default::default() },
                 texture_atlas: texture_atlas_handle.clone(),
                 transform,
                 ..default()
             },
-        ))
-        .insert(ProvidesIllumination::new(30, 60, None))
-        .insert(map::MapPoint::new(point));    
-    }
+            spawner::creature_bundle(hero, player_start),
+            // ProvidesIllumination::new(30, 60, None),
+            Player,
+        ));
 
-    commands.insert_resource(map_builder);
+        let transform = get_tilemap_center_transform(&map_size, &grid_size, &map_type, 2.0);
+        spawner::spawn_monsters(&mut commands, &map_builder, texture_atlas_handle, transform, monsters);
+
+        for (point, c) in map_builder.entity_spawns.clone() {
+            let Some(light) = map_builder.legend.light(c) else {
+                continue;
+            };
+            let mut transform = get_tilemap_center_transform(&map_size, &grid_size, &map_type, 3.0);
+            let in_b_e_t = map_builder.map.to_bevy_ecs_tilemap(point.x, point.y);
+            transform.translation.x += (in_b_e_t.x * 32) as f32;
+            transform.translation.y += (in_b_e_t.y * 32) as f32;
+            commands.spawn((
+                SpriteSheetBundle {
+                    sprite: TextureAtlasSprite { index: light.sprite_index, ..Default::default() },
+                    texture_atlas: lights_atlas_handle.clone(),
+                    transform,
+                    ..default()
+                },
+            ))
+            .insert(light.illumination())
+            .insert(map::MapPoint::new(point))
+            .insert(EncounterEntity);
+        }
+    } else {
+        let transform = get_tilemap_center_transform(&map_size, &grid_size, &map_type, 0.0);
+        dress_restored(&mut commands, &restored, &map_builder, transform, texture_atlas_handle, lights_atlas_handle);
+    }
 
     // Add atlas to array texture loader so it's preprocessed before we need to use it.
     // Only used when the atlas feature is off and we are using array textures.
@@ -210,20 +283,139 @@ fn startup(
     }
 }
 
+// Gives the creatures and lights of a loaded save their sprites: creatures
+// from the monsters atlas, everything else from the ground atlas
+#[allow(clippy::type_complexity)]
+fn dress_restored(
+    commands: &mut Commands,
+    restored: &Query<(Entity, &map::MapPoint, &Restored, Option<&StatBlock>, Option<&Monster>)>,
+    map_builder: &MapBuilder,
+    origin: Transform,
+    creatures_atlas: Handle<TextureAtlas>,
+    lights_atlas: Handle<TextureAtlas>,
+) {
+    for (entity, map_point, restored, stat_block, monster) in restored.iter() {
+        commands.entity(entity).remove::<Restored>();
+        let Some(index) = restored.sprite_index else {
+            continue;
+        };
+        let (texture_atlas, z) = match stat_block {
+            Some(_) => (creatures_atlas.clone(), 2.0),
+            None => (lights_atlas.clone(), 3.0),
+        };
+        let mut transform = origin;
+        let in_b_e_t = map_builder.map.to_bevy_ecs_tilemap(map_point.0.x, map_point.0.y);
+        transform.translation.x += (in_b_e_t.x * 32) as f32;
+        transform.translation.y += (in_b_e_t.y * 32) as f32;
+        transform.translation.z = z;
+        // Monsters stay hidden until the player's side makes them out
+        let visibility = if monster.is_some() { Visibility::Hidden } else { Visibility::Inherited };
+        commands.entity(entity).insert(SpriteSheetBundle {
+            sprite: TextureAtlasSprite { index, ..Default::default() },
+            texture_atlas,
+            transform,
+            visibility,
+            ..default()
+        });
+    }
+}
+
+// Plays encounters one seed after another with the AI on both sides,
+// printing how each went
+fn run_headless(source: &MapSource, seed: u64, headless: HeadlessOptions) {
+    let mut victories = 0;
+    for run in 0..headless.runs as u64 {
+        let seed = seed.wrapping_add(run);
+        let map_builder = match source.build(seed) {
+            Ok(map_builder) => map_builder,
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        };
+        let mut simulation = match Simulation::new(map_builder, seed, headless.max_rounds) {
+            Ok(simulation) => simulation,
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        };
+        let report = simulation.run();
+        if report.outcome == Some(SimulationOutcome::Victory) {
+            victories += 1;
+        }
+        println!("Seed {}: {}", seed, report);
+    }
+    if headless.runs > 1 {
+        println!("Won {} of {} encounters", victories, headless.runs);
+    }
+}
+
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
+    let options = match LaunchOptions::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(UsageError::Help) => {
+            println!("{}", UsageError::Help);
+            return;
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+    };
+    // A seed plays a session again; otherwise one is picked at random
+    let seed = options.seed.unwrap_or_else(|| RandomNumberGenerator::new().next_u64());
+    if let Some(headless) = options.headless {
+        run_headless(&options.source, seed, headless);
+        return;
+    }
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: String::from("Adventure Encounters"),
                 ..Default::default()
             }),
             ..default()
-        }).set(ImagePlugin::default_nearest()))
+        })
+        .set(ImagePlugin::default_nearest())
+        // Saving a map file while the game runs reloads the encounter
+        .set(AssetPlugin { watch_for_changes: true, ..default() }))
+        .insert_resource(Seed(seed))
+        .insert_resource(GameRng::seeded(seed))
         .add_plugin(TilemapPlugin)
+        .add_plugin(EncounterPlugin)
+        .add_plugin(TurnPlugin)
+        .add_plugin(MovementPlugin)
+        .add_plugin(CombatPlugin)
+        .add_plugin(ClockPlugin)
+        .add_plugin(SavePlugin)
+        .init_resource::<CursorMapPoint>()
         .add_startup_system(startup)
+        .add_system(spawn_encounter.run_if(on_event::<EncounterLoadedEvent>()))
         .add_system(helpers::camera::movement)
-        .add_system(systems::illumination::illumination_system)
-        .add_system(systems::field_of_view::field_of_view_system.after(illumination_system))
+        .add_system(helpers::camera::cursor_map_point_system.in_set(EncounterSet))
+        .add_system(systems::illumination::illumination_system.in_set(EncounterSet))
+        .add_system(systems::field_of_view::field_of_view_system.after(illumination_system).in_set(EncounterSet))
         .add_system(systems::player_render_system.after(systems::field_of_view::field_of_view_system))
-        .run();
-}
\ No newline at end of file
+        .add_system(systems::spawner::monster_visibility_system.after(systems::field_of_view::field_of_view_system).in_set(EncounterSet));
+
+    match options.source {
+        MapSource::Generated { .. } => match options.source.build(seed) {
+            Ok(map_builder) => {
+                app.insert_resource(map_builder);
+                app.world.send_event(EncounterLoadedEvent);
+            }
+            Err(error) => {
+                app.insert_resource(MapLoadFailed(error.to_string()));
+            }
+        },
+        // A map file from assets/maps is loaded again whenever it's saved
+        MapSource::File(path) => {
+            let map_file = app.world.resource::<AssetServer>().load(path);
+            app.insert_resource(EncounterMap(map_file));
+        }
+    }
+
+    app.run();
+}
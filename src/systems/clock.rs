@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    *,
+    illumination::{illumination_system, ProvidesIllumination},
+    turn::end_turn_system,
+};
+
+// Combat is played in rounds of six seconds, exploration in turns of a minute
+pub const SECONDS_PER_ROUND: u64 = 6;
+pub const SECONDS_PER_EXPLORATION_TURN: u64 = 60;
+
+// Time passed in the encounter, which lights burn down by
+#[derive(Resource, Copy, Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct GameClock {
+    pub seconds: u64,
+}
+
+impl GameClock {
+    pub fn minutes(&self) -> u64 {
+        self.seconds / 60
+    }
+}
+
+// Sent to move the clock on, at the end of each round or exploration turn
+pub struct TimePassedEvent {
+    pub seconds: u64,
+}
+
+pub struct ClockPlugin;
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        // A round that ends reaches the clock on the next frame, before the
+        // lights are redrawn
+        app.init_resource::<GameClock>()
+            .add_event::<TimePassedEvent>()
+            .add_system(clock_system.before(end_turn_system).before(illumination_system));
+    }
+}
+
+// Moves the clock on and burns every light down by each minute it passes
+pub fn clock_system(
+    mut time_passed: EventReader<TimePassedEvent>,
+    mut clock: ResMut<GameClock>,
+    mut lights: Query<&mut ProvidesIllumination>,
+) {
+    let before = clock.minutes();
+    for event in time_passed.iter() {
+        clock.seconds += event.seconds;
+    }
+    let minutes = clock.minutes() - before;
+    if minutes == 0 {
+        return;
+    }
+    for mut light in lights.iter_mut() {
+        light.burn(minutes as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass(world: &mut World, schedule: &mut Schedule, seconds: u64) {
+        world.send_event(TimePassedEvent { seconds });
+        schedule.run(world);
+    }
+
+    #[test]
+    fn lights_burn_down_a_minute_at_a_time() {
+        let mut schedule = Schedule::new();
+        schedule.add_system(clock_system);
+        let mut world = World::new();
+        world.init_resource::<GameClock>();
+        world.init_resource::<Events<TimePassedEvent>>();
+        let torch = world.spawn(ProvidesIllumination::new(20, 20, Some(12))).id();
+        let sun = world.spawn(ProvidesIllumination::new(20, 20, None)).id();
+        world.get_mut::<ProvidesIllumination>(torch).unwrap().is_dirty = false;
+        let torch_minutes = |world: &World| world.get::<ProvidesIllumination>(torch).unwrap().duration;
+
+        for _ in 0..9 {
+            pass(&mut world, &mut schedule, SECONDS_PER_ROUND);
+        }
+        assert_eq!(torch_minutes(&world), Some(12));
+        pass(&mut world, &mut schedule, SECONDS_PER_ROUND);
+        assert_eq!(world.resource::<GameClock>().seconds, 60);
+        assert_eq!(torch_minutes(&world), Some(11));
+        assert!(!world.get::<ProvidesIllumination>(torch).unwrap().is_dirty);
+
+        // Guttering and going out both change what the torch lights
+        pass(&mut world, &mut schedule, SECONDS_PER_EXPLORATION_TURN);
+        assert_eq!(torch_minutes(&world), Some(10));
+        assert!(world.get::<ProvidesIllumination>(torch).unwrap().is_guttering());
+        assert!(world.get::<ProvidesIllumination>(torch).unwrap().is_dirty);
+        pass(&mut world, &mut schedule, 30 * SECONDS_PER_EXPLORATION_TURN);
+        assert!(world.get::<ProvidesIllumination>(torch).unwrap().is_burned_out());
+        assert_eq!(world.get::<ProvidesIllumination>(sun).unwrap().duration, None);
+    }
+}
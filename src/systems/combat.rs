@@ -0,0 +1,380 @@
+use crate::prelude::{
+    *,
+    camera::CursorMapPoint,
+    cover::{cover_between, Cover},
+    creature::{AttackProfile, AttackRange, HitPoints, StatBlock},
+    distance::{distance_between_points, DiagonalRule},
+    encounter::EncounterSet,
+    map::{Map, MapPoint, SeenLevel},
+    map_builder::MapBuilder,
+    movement::Movement,
+    rng::GameRng,
+    turn::{CurrentActor, TurnState},
+};
+
+use super::{
+    Monster,
+    Player,
+    cover_overlay::{cover_icon_system, spawn_cover_icons},
+};
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Advantage {
+    #[default]
+    Normal,
+    Advantage,
+    Disadvantage,
+}
+
+impl Advantage {
+    // Any amount of advantage and disadvantage cancel each other out
+    pub fn combine(&self, other: Advantage) -> Advantage {
+        match (self, other) {
+            (Advantage::Normal, other) => other,
+            (this, Advantage::Normal) => *this,
+            (this, other) if *this == other => other,
+            _ => Advantage::Normal,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum AttackOutcome {
+    Hit,
+    CriticalHit,
+    Miss,
+    CriticalMiss,
+    OutOfRange,
+    BehindFullCover,
+}
+
+// A request for `attacker` to use its `attack`th attack on `target`
+#[derive(Copy, Clone, Debug)]
+pub struct AttackEvent {
+    pub attacker: Entity,
+    pub target: Entity,
+    pub attack: usize,
+    pub advantage: Advantage,
+}
+
+#[derive(Clone, Debug)]
+pub struct AttackResultEvent {
+    pub attacker: Entity,
+    pub target: Entity,
+    pub attack_name: String,
+    pub advantage: Advantage,
+    pub natural_roll: i32,
+    pub total: i32,
+    pub cover: Cover,
+    pub target_armor_class: i32,
+    pub outcome: AttackOutcome,
+    pub damage: i32,
+}
+
+pub struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<AttackEvent>()
+            .add_event::<AttackResultEvent>()
+            .add_startup_system(spawn_cover_icons)
+            .add_system(cover_icon_system.in_set(EncounterSet))
+            .add_system(player_attack_system.in_set(OnUpdate(TurnState::PlayerTurn)).in_set(EncounterSet))
+            .add_system(attack_system.after(player_attack_system).in_set(EncounterSet))
+            .add_system(attack_log_system.after(attack_system))
+            .add_system(slay_system.after(attack_system));
+    }
+}
+
+// Rolls a d20 twice with advantage or disadvantage, keeping the better or worse
+pub fn roll_d20(advantage: Advantage, rng: &mut RandomNumberGenerator) -> i32 {
+    let first = rng.roll_dice(1, 20);
+    match advantage {
+        Advantage::Normal => first,
+        Advantage::Advantage => first.max(rng.roll_dice(1, 20)),
+        Advantage::Disadvantage => first.min(rng.roll_dice(1, 20)),
+    }
+}
+
+// Rolls an attack's damage dice. A critical hit rolls the dice twice
+pub fn roll_damage(mut dice: DiceType, critical: bool, rng: &mut RandomNumberGenerator) -> i32 {
+    if critical {
+        dice.n_dice *= 2;
+    }
+    rng.roll(dice).max(0)
+}
+
+// Whether a target `distance` away is within reach, in normal range or only in long range
+pub fn attack_range_advantage(attack: &AttackProfile, attacker: Point, target: Point, rule: DiagonalRule) -> Option<Advantage> {
+    match attack.range {
+        AttackRange::Melee { reach } => {
+            // Reach always counts diagonals as adjacent, whatever the movement rule
+            let distance = distance_between_points(attacker, target, DiagonalRule::Chebyshev);
+            (distance <= reach as f32).then_some(Advantage::Normal)
+        }
+        AttackRange::Ranged { normal, long } => {
+            let distance = distance_between_points(attacker, target, rule);
+            if distance <= normal as f32 {
+                Some(Advantage::Normal)
+            } else if distance <= long as f32 {
+                Some(Advantage::Disadvantage)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// Advantage from who can make out whom, using each creature's view of the other's tile.
+// A target the attacker can't make out is heavily obscured, giving disadvantage, and an
+// attacker the target can't make out is unseen, giving advantage.
+pub fn lighting_advantage(map: &Map, attacker: Entity, attacker_point: Point, target: Entity, target_point: Point) -> Advantage {
+    let can_make_out = |viewer: Entity, point: Point| {
+        map.tiles[map.map_idx(point.x, point.y)]
+            .seen_by
+            .get(&viewer)
+            .is_some_and(SeenLevel::can_make_out)
+    };
+    let mut advantage = Advantage::Normal;
+    if !can_make_out(attacker, target_point) {
+        advantage = advantage.combine(Advantage::Disadvantage);
+    }
+    if !can_make_out(target, attacker_point) {
+        advantage = advantage.combine(Advantage::Advantage);
+    }
+    advantage
+}
+
+pub fn attack_system(
+    mut attacks: EventReader<AttackEvent>,
+    mut results: EventWriter<AttackResultEvent>,
+    attackers: Query<(&StatBlock, &MapPoint)>,
+    mut targets: Query<(&StatBlock, &MapPoint, &mut HitPoints)>,
+    mut rng: ResMut<GameRng>,
+    mb: Res<MapBuilder>,
+) {
+    for event in attacks.iter() {
+        let Ok((attacker_stats, attacker_point)) = attackers.get(event.attacker) else {
+            continue;
+        };
+        let Some(attack) = attacker_stats.attacks.get(event.attack) else {
+            continue;
+        };
+        let Ok((target_stats, target_point, mut hit_points)) = targets.get_mut(event.target) else {
+            continue;
+        };
+        let mut result = AttackResultEvent {
+            attacker: event.attacker,
+            target: event.target,
+            attack_name: attack.name.clone(),
+            advantage: event.advantage,
+            natural_roll: 0,
+            total: 0,
+            cover: cover_between(&mb.map, attacker_point.0, target_point.0),
+            target_armor_class: target_stats.armor_class,
+            outcome: AttackOutcome::OutOfRange,
+            damage: 0,
+        };
+        let Some(range_advantage) = attack_range_advantage(attack, attacker_point.0, target_point.0, mb.map.diagonal_rule) else {
+            results.send(result);
+            continue;
+        };
+        if result.cover == Cover::Full {
+            result.outcome = AttackOutcome::BehindFullCover;
+            results.send(result);
+            continue;
+        }
+        result.target_armor_class += result.cover.armor_class_bonus();
+        let lighting = lighting_advantage(&mb.map, event.attacker, attacker_point.0, event.target, target_point.0);
+        result.advantage = event.advantage.combine(range_advantage).combine(lighting);
+        result.natural_roll = roll_d20(result.advantage, &mut rng.0);
+        result.total = result.natural_roll + attack.to_hit;
+        // A natural 20 always hits and a natural 1 always misses
+        result.outcome = match result.natural_roll {
+            20 => AttackOutcome::CriticalHit,
+            1 => AttackOutcome::CriticalMiss,
+            _ if result.total >= result.target_armor_class => AttackOutcome::Hit,
+            _ => AttackOutcome::Miss,
+        };
+        if matches!(result.outcome, AttackOutcome::Hit | AttackOutcome::CriticalHit) {
+            result.damage = roll_damage(attack.damage, result.outcome == AttackOutcome::CriticalHit, &mut rng.0);
+            hit_points.current -= result.damage;
+        }
+        results.send(result);
+    }
+}
+
+// Right clicking a monster attacks it with the first attack that can reach,
+// as long as the player has an attack left this turn
+pub fn player_attack_system(
+    mouse_input: Res<Input<MouseButton>>,
+    cursor_map_point: Res<CursorMapPoint>,
+    current_actor: Res<CurrentActor>,
+    mut players: Query<(&StatBlock, &MapPoint, &mut Movement), With<Player>>,
+    monsters: Query<(Entity, &MapPoint), With<Monster>>,
+    mut attacks: EventWriter<AttackEvent>,
+    mb: Res<MapBuilder>,
+) {
+    if !mouse_input.just_pressed(MouseButton::Right) {
+        return;
+    }
+    let (Some(attacker), Some(cursor)) = (current_actor.0, cursor_map_point.0) else {
+        return;
+    };
+    let Ok((stat_block, map_point, mut movement)) = players.get_mut(attacker) else {
+        return;
+    };
+    if movement.attacks == 0 {
+        return;
+    }
+    let Some((target, _)) = monsters.iter().find(|(_, monster_point)| monster_point.0 == cursor) else {
+        return;
+    };
+    let chosen = stat_block
+        .attacks
+        .iter()
+        .position(|attack| attack_range_advantage(attack, map_point.0, cursor, mb.map.diagonal_rule).is_some());
+    if let Some(attack) = chosen {
+        movement.attacks -= 1;
+        attacks.send(AttackEvent { attacker, target, attack, advantage: Advantage::Normal });
+    }
+}
+
+pub fn attack_log_system(
+    mut results: EventReader<AttackResultEvent>,
+    names: Query<&StatBlock>,
+) {
+    for result in results.iter() {
+        let name = |entity| names.get(entity).map(|x| x.name.clone()).unwrap_or_default();
+        info!(
+            "{} attacks {} with {} ({:?}): rolled {} for {} against AC {} ({:?} cover), {:?} for {} damage",
+            name(result.attacker),
+            name(result.target),
+            result.attack_name,
+            result.advantage,
+            result.natural_roll,
+            result.total,
+            result.target_armor_class,
+            result.cover,
+            result.outcome,
+            result.damage
+        );
+    }
+}
+
+// Monsters reduced to zero hit points are removed from the encounter
+#[allow(clippy::type_complexity)]
+pub fn slay_system(
+    mut commands: Commands,
+    monsters: Query<(Entity, &HitPoints), (With<Monster>, Changed<HitPoints>)>,
+) {
+    for (entity, hit_points) in monsters.iter() {
+        if hit_points.current <= 0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::helpers::map_builder::themes::MapTheme;
+
+    fn attack(range: AttackRange) -> AttackProfile {
+        AttackProfile { name: "Test".to_string(), to_hit: 0, damage: DiceType::new(1, 6, 0), range }
+    }
+
+    #[test]
+    fn advantage_and_disadvantage_cancel() {
+        assert_eq!(Advantage::Normal.combine(Advantage::Advantage), Advantage::Advantage);
+        assert_eq!(Advantage::Disadvantage.combine(Advantage::Normal), Advantage::Disadvantage);
+        assert_eq!(Advantage::Advantage.combine(Advantage::Advantage), Advantage::Advantage);
+        assert_eq!(Advantage::Advantage.combine(Advantage::Disadvantage), Advantage::Normal);
+    }
+
+    #[test]
+    fn unseen_attackers_and_obscured_targets() {
+        let mut map = Map::new(4, 1, MapTheme::named("DungeonTheme").unwrap());
+        let (attacker, target) = (Entity::from_raw(1), Entity::from_raw(2));
+        let (attacker_point, target_point) = (Point::new(0, 0), Point::new(3, 0));
+        fn sees(map: &mut Map, viewer: Entity, point: Point, level: SeenLevel) {
+            let idx = map.map_idx(point.x, point.y);
+            map.tiles[idx].seen_by.insert(viewer, level);
+        }
+        sees(&mut map, attacker, target_point, SeenLevel::Shadowy);
+        sees(&mut map, target, attacker_point, SeenLevel::Bright);
+        assert_eq!(lighting_advantage(&map, attacker, attacker_point, target, target_point), Advantage::Normal);
+        sees(&mut map, target, attacker_point, SeenLevel::Darkness);
+        assert_eq!(lighting_advantage(&map, attacker, attacker_point, target, target_point), Advantage::Advantage);
+        sees(&mut map, attacker, target_point, SeenLevel::Darkness);
+        assert_eq!(lighting_advantage(&map, attacker, attacker_point, target, target_point), Advantage::Normal);
+        sees(&mut map, target, attacker_point, SeenLevel::Bright);
+        assert_eq!(lighting_advantage(&map, attacker, attacker_point, target, target_point), Advantage::Disadvantage);
+    }
+
+    #[test]
+    fn the_player_attacks_once_a_turn() {
+        use crate::helpers::map_builder::MapBuilder;
+        use crate::systems::movement::reset_movement_system;
+        use crate::systems::turn::BeginTurnEvent;
+
+        let theme = MapTheme::named("DungeonTheme").unwrap();
+        let mut world = World::new();
+        world.insert_resource(MapBuilder::from_map(Map::new(3, 1, theme), theme));
+        world.insert_resource(CursorMapPoint(Some(Point::new(1, 0))));
+        world.init_resource::<Input<MouseButton>>();
+        world.init_resource::<Events<AttackEvent>>();
+        world.init_resource::<Events<BeginTurnEvent>>();
+        let hero = StatBlock::load("hero").unwrap();
+        let movement = hero.movement();
+        let player = world.spawn((hero, MapPoint::new(Point::new(0, 0)), movement, Player)).id();
+        world.spawn((MapPoint::new(Point::new(1, 0)), Monster));
+        world.insert_resource(CurrentActor(Some(player)));
+        let mut schedule = Schedule::new();
+        schedule.add_systems((reset_movement_system, player_attack_system).chain());
+        let right_click = |world: &mut World, schedule: &mut Schedule| {
+            let mut mouse_input = world.resource_mut::<Input<MouseButton>>();
+            mouse_input.release(MouseButton::Right);
+            mouse_input.clear();
+            mouse_input.press(MouseButton::Right);
+            schedule.run(world);
+            world.resource::<Events<AttackEvent>>().len()
+        };
+
+        assert_eq!(right_click(&mut world, &mut schedule), 1);
+        // A second attack in the same turn is refused
+        assert_eq!(right_click(&mut world, &mut schedule), 1);
+        world.send_event(BeginTurnEvent { actor: player, round: 2 });
+        assert_eq!(right_click(&mut world, &mut schedule), 2);
+    }
+
+    #[test]
+    fn critical_damage_doubles_the_dice_but_not_the_bonus() {
+        let mut rng = RandomNumberGenerator::seeded(3);
+        for _ in 0..100 {
+            let normal = roll_damage(DiceType::new(2, 6, 3), false, &mut rng);
+            assert!((5..=15).contains(&normal));
+            let critical = roll_damage(DiceType::new(2, 6, 3), true, &mut rng);
+            assert!((7..=27).contains(&critical));
+        }
+    }
+
+    #[test]
+    fn ranged_attacks_have_disadvantage_at_long_range() {
+        let bow = attack(AttackRange::Ranged { normal: 80, long: 320 });
+        let origin = Point::new(0, 0);
+        let rule = DiagonalRule::Alternating;
+        assert_eq!(attack_range_advantage(&bow, origin, Point::new(16, 0), rule), Some(Advantage::Normal));
+        assert_eq!(attack_range_advantage(&bow, origin, Point::new(17, 0), rule), Some(Advantage::Disadvantage));
+        assert_eq!(attack_range_advantage(&bow, origin, Point::new(65, 0), rule), None);
+    }
+
+    #[test]
+    fn melee_reaches_diagonals() {
+        let sword = attack(AttackRange::Melee { reach: 5 });
+        let origin = Point::new(4, 4);
+        assert!(attack_range_advantage(&sword, origin, Point::new(5, 5), DiagonalRule::Euclidean).is_some());
+        assert!(attack_range_advantage(&sword, origin, Point::new(6, 5), DiagonalRule::Euclidean).is_none());
+    }
+}
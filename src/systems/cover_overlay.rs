@@ -0,0 +1,73 @@
+use crate::prelude::{
+    *,
+    camera::CursorMapPoint,
+    cover::Cover,
+    map::ObjectsMapLayer,
+    map_builder::MapBuilder,
+};
+
+// A shield on one side of the hovered tile, showing the cover the
+// neighbouring tile on that side gives against attacks from beyond it
+#[derive(Component)]
+pub struct CoverIcon {
+    pub side: Point,
+}
+
+pub fn spawn_cover_icons(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+) {
+    let texture_handle: Handle<Image> = asset_server.load("cover.png");
+    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(32.0, 32.0), 3, 1, None, None);
+    let texture_atlas_handle = texture_atlases.add(texture_atlas);
+    for side in [Point::new(0, -1), Point::new(1, 0), Point::new(0, 1), Point::new(-1, 0)] {
+        commands.spawn(SpriteSheetBundle {
+            texture_atlas: texture_atlas_handle.clone(),
+            transform: Transform::from_scale(Vec3::splat(0.5)),
+            visibility: Visibility::Hidden,
+            ..default()
+        })
+        .insert(CoverIcon { side });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn cover_icon_system(
+    cursor_map_point: Res<CursorMapPoint>,
+    mut icons: Query<(&CoverIcon, &mut TextureAtlasSprite, &mut Transform, &mut Visibility)>,
+    layers: Query<(&TilemapGridSize, &Transform), (With<ObjectsMapLayer>, Without<CoverIcon>)>,
+    mb: Res<MapBuilder>,
+) {
+    if !cursor_map_point.is_changed() {
+        return;
+    }
+    let Ok((grid_size, map_transform)) = layers.get_single() else {
+        return;
+    };
+    // Only tiles a creature could stand on are worth showing cover for
+    let hovered = cursor_map_point.0.filter(|point| mb.map.can_enter_tile(*point));
+    for (icon, mut sprite, mut transform, mut visibility) in icons.iter_mut() {
+        let cover = match hovered.map(|point| point + icon.side) {
+            Some(neighbor) if mb.map.in_bounds(neighbor) => mb.map.tiles[mb.map.map_idx(neighbor.x, neighbor.y)].cover,
+            _ => Cover::None,
+        };
+        let (Some(point), Some(index)) = (hovered, match cover {
+            Cover::None => None,
+            Cover::Half => Some(0),
+            Cover::ThreeQuarters => Some(1),
+            Cover::Full => Some(2),
+        }) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        sprite.index = index;
+        // Sit just inside the edge shared with the neighbour, above the fog of war.
+        // Map y runs down the screen while bevy_ecs_tilemap's runs up.
+        let tile_pos = mb.map.to_bevy_ecs_tilemap(point.x, point.y);
+        transform.translation.x = map_transform.translation.x + (tile_pos.x as f32 + icon.side.x as f32 * 0.35) * grid_size.x;
+        transform.translation.y = map_transform.translation.y + (tile_pos.y as f32 - icon.side.y as f32 * 0.35) * grid_size.y;
+        transform.translation.z = 4.0;
+        *visibility = Visibility::Inherited;
+    }
+}
@@ -0,0 +1,226 @@
+use std::path::Path;
+
+use bevy::asset::{AssetLoader, LoadContext, LoadState, LoadedAsset};
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+
+use crate::prelude::{
+    *,
+    map_builder::{MapBuilder, custom::{CustomFileBuilder, CustomMap, MapLoadError}},
+    clock::GameClock,
+    turn::{CurrentActor, EncounterMode, Round, TurnOrder, TurnState},
+};
+
+use super::{error_screen::{MapLoadFailed, spawn_error_screen}, movement_overlay::MovementRange};
+
+// A custom map file as the asset server loads it. A file that doesn't parse
+// still loads, so the error can be shown and fixed while the game runs.
+#[derive(TypeUuid)]
+#[uuid = "081177b1-966f-4482-8159-34aafe5927a1"]
+pub struct CustomMapFile(pub Result<CustomMap, MapLoadError>);
+
+#[derive(Default)]
+pub struct CustomMapLoader;
+
+impl AssetLoader for CustomMapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let custom_map = ron::de::from_bytes(bytes)
+                .map_err(|source| MapLoadError::Parse { path: load_context.path().to_path_buf(), source });
+            load_context.set_default_asset(LoadedAsset::new(CustomMapFile(custom_map)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map.ron"]
+    }
+}
+
+// The map file the encounter is built from, rebuilt whenever the file changes
+#[derive(Resource)]
+pub struct EncounterMap(pub Handle<CustomMapFile>);
+
+// Sent once a new MapBuilder is in place and its encounter needs spawning
+pub struct EncounterLoadedEvent;
+
+// Everything spawned for an encounter, cleared away when its map is reloaded
+#[derive(Component)]
+pub struct EncounterEntity;
+
+// Systems that need the encounter's map, which doesn't exist while it's
+// loading or when it failed to load
+#[derive(SystemSet, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct EncounterSet;
+
+pub struct EncounterPlugin;
+
+impl Plugin for EncounterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<CustomMapFile>()
+            .init_asset_loader::<CustomMapLoader>()
+            .add_event::<EncounterLoadedEvent>()
+            .configure_set(EncounterSet.run_if(resource_exists::<MapBuilder>()))
+            .add_system(custom_map_reload_system.in_base_set(CoreSet::PreUpdate))
+            .add_system(spawn_error_screen.run_if(resource_exists_and_changed::<MapLoadFailed>()))
+            .add_system(export_map_system.in_set(EncounterSet));
+    }
+}
+
+// Builds the encounter's map each time its file finishes loading or is saved,
+// throwing away whatever was spawned for the last version
+#[allow(clippy::too_many_arguments)]
+pub fn custom_map_reload_system(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<CustomMapFile>>,
+    encounter_map: Option<Res<EncounterMap>>,
+    files: Res<Assets<CustomMapFile>>,
+    asset_server: Res<AssetServer>,
+    failure: Option<Res<MapLoadFailed>>,
+    encounter_entities: Query<Entity, With<EncounterEntity>>,
+    tilemaps: Query<&TileStorage, With<EncounterEntity>>,
+    mut loaded: EventWriter<EncounterLoadedEvent>,
+    mut next_state: ResMut<NextState<TurnState>>,
+) {
+    let Some(encounter_map) = encounter_map else {
+        return;
+    };
+    let changed = asset_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => *handle == encounter_map.0,
+        AssetEvent::Removed { .. } => false,
+    });
+    let result = if changed {
+        match files.get(&encounter_map.0) {
+            Some(CustomMapFile(Ok(custom_map))) => CustomFileBuilder::build(custom_map).map_err(|error| error.to_string()),
+            Some(CustomMapFile(Err(error))) => Err(error.to_string()),
+            None => return,
+        }
+    } else if failure.is_none() && asset_server.get_load_state(&encounter_map.0) == LoadState::Failed {
+        let path = asset_server.get_handle_path(&encounter_map.0).map(|path| path.path().to_path_buf()).unwrap_or_default();
+        Err(unreadable_map_file(&asset_server, &path))
+    } else {
+        return;
+    };
+
+    // Tiles aren't children of their tilemap, so they go separately
+    for storage in tilemaps.iter() {
+        for tile in storage.iter().flatten() {
+            commands.entity(*tile).despawn();
+        }
+    }
+    for entity in encounter_entities.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.insert_resource(TurnOrder::default());
+    commands.insert_resource(Round::default());
+    commands.insert_resource(CurrentActor::default());
+    commands.insert_resource(EncounterMode::default());
+    commands.insert_resource(GameClock::default());
+    commands.insert_resource(MovementRange::default());
+    next_state.set(TurnState::RollInitiative);
+    match result {
+        Ok(map_builder) => {
+            commands.remove_resource::<MapLoadFailed>();
+            commands.insert_resource(map_builder);
+            loaded.send(EncounterLoadedEvent);
+        }
+        Err(error) => {
+            commands.remove_resource::<MapBuilder>();
+            commands.insert_resource(MapLoadFailed(error));
+        }
+    }
+}
+
+// The loader keeps a file that doesn't parse, so a failed load means the file
+// couldn't be read. The asset server only logs why, so it is asked again.
+fn unreadable_map_file(asset_server: &AssetServer, path: &Path) -> String {
+    match asset_server.asset_io().get_metadata(path) {
+        Err(error) => format!("Couldn't read {}: {}", path.display(), error),
+        Ok(metadata) if metadata.is_dir() => format!("Couldn't read {}: it is a directory", path.display()),
+        Ok(_) => format!("Couldn't read {}", path.display()),
+    }
+}
+
+// Where F2 saves the map being played, to be loaded or edited later
+pub const EXPORT_PATH: &str = "assets/maps/exported.map.ron";
+
+pub fn export_map_system(keyboard_input: Res<Input<KeyCode>>, mb: Res<MapBuilder>) {
+    if !keyboard_input.just_pressed(KeyCode::F2) {
+        return;
+    }
+    match CustomFileBuilder::save(&mb, EXPORT_PATH) {
+        Ok(()) => info!("Saved the map to {}", EXPORT_PATH),
+        Err(error) => error!("Couldn't save the map to {}: {}", EXPORT_PATH, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    // Runs a windowless app until the encounter map either builds or fails
+    fn load(path: &str) -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default())
+            .add_plugin(bevy::input::InputPlugin)
+            .add_state::<TurnState>()
+            .add_plugin(EncounterPlugin);
+        let map_file = app.world.resource::<AssetServer>().load(path);
+        app.insert_resource(EncounterMap(map_file));
+        for _ in 0..500 {
+            app.update();
+            if app.world.contains_resource::<MapBuilder>() || app.world.contains_resource::<MapLoadFailed>() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        app
+    }
+
+    #[test]
+    fn map_files_load_through_the_asset_server() {
+        let app = load("maps/campfire.map.ron");
+        let mb = app.world.resource::<MapBuilder>();
+        assert_eq!(mb.player_start, Point::new(20, 5));
+        assert!(!app.world.contains_resource::<MapLoadFailed>());
+    }
+
+    #[test]
+    fn missing_map_files_are_reported() {
+        let app = load("maps/no_such_map.map.ron");
+        assert!(!app.world.contains_resource::<MapBuilder>());
+        let message = &app.world.resource::<MapLoadFailed>().0;
+        assert!(message.starts_with("Couldn't read maps/no_such_map.map.ron: path not found"), "{}", message);
+    }
+
+    #[test]
+    fn saving_the_file_rebuilds_the_encounter() {
+        let mut app = load("maps/campfire.map.ron");
+        let stale = app.world.spawn(EncounterEntity).id();
+        let handle = app.world.resource::<EncounterMap>().0.clone();
+        let custom_map = ron::from_str(
+            "(width: 3, height: 1, theme: ForestTheme, amulet_start: (0, 0), tiles: \"_@_\")"
+        ).unwrap();
+        app.world.resource_mut::<Assets<CustomMapFile>>().get_mut(&handle).unwrap().0 = Ok(custom_map);
+        app.update();
+        app.update();
+        assert_eq!(app.world.resource::<MapBuilder>().player_start, Point::new(1, 0));
+        assert!(app.world.get_entity(stale).is_none());
+
+        let custom_map = ron::from_str(
+            "(width: 3, height: 1, theme: ForestTheme, amulet_start: (0, 0), tiles: \"_@?\")"
+        ).unwrap();
+        app.world.resource_mut::<Assets<CustomMapFile>>().get_mut(&handle).unwrap().0 = Ok(custom_map);
+        app.update();
+        app.update();
+        assert!(!app.world.contains_resource::<MapBuilder>());
+        assert!(app.world.resource::<MapLoadFailed>().0.starts_with("Unknown glyph '?'"));
+    }
+}
@@ -0,0 +1,28 @@
+use crate::prelude::*;
+
+use super::encounter::EncounterEntity;
+
+// Why the encounter couldn't be set up, shown in place of the map
+#[derive(Resource)]
+pub struct MapLoadFailed(pub String);
+
+pub fn spawn_error_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    failure: Res<MapLoadFailed>,
+) {
+    let font = asset_server.load("fonts/DejaVuSansMono.ttf");
+    commands.spawn(
+        TextBundle::from_sections([
+            TextSection::new("The map couldn't be loaded\n\n", TextStyle { font: font.clone(), font_size: 32.0, color: Color::ORANGE_RED }),
+            TextSection::new(failure.0.clone(), TextStyle { font, font_size: 20.0, color: Color::WHITE }),
+        ])
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect { left: Val::Px(40.0), top: Val::Px(40.0), ..default() },
+            max_size: Size::width(Val::Percent(90.0)),
+            ..default()
+        }),
+    )
+    .insert(EncounterEntity);
+}
@@ -1,10 +1,13 @@
-use crate::prelude::{*, map_builder::MapBuilder, map::{FogOfWarMapLayer, SeenLevel}, distance::distance_between_points};
+use crate::prelude::{*, map_builder::MapBuilder, map::{FogOfWarMapLayer, SeenLevel, sorted_points}, distance::distance_between_points};
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
 use super::illumination::IlluminationLevel;
+use super::Player;
 
-#[derive(Component, Clone, Debug, PartialEq)]
+#[derive(Component, Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct FieldOfView{
+    #[serde(with = "sorted_points")]
     pub visible_tiles : HashSet<Point>,
     pub dark_vision: Option<u16>,
     pub dim_vision: Option<u16>,
@@ -36,10 +39,12 @@ impl FieldOfView {
 pub fn field_of_view_system(
     mut fovs: Query<(Entity, &map::MapPoint, &mut FieldOfView)>,
     mut mb: ResMut<MapBuilder>,
-    mut fog_of_war: Query<(&mut TileStorage, &FogOfWarMapLayer)>,
+    fog_of_war: Query<(&TileStorage, &FogOfWarMapLayer)>,
+    players: Query<(), With<Player>>,
     mut commands: Commands
 ) {
     let mut changed: HashSet<Point> = HashSet::new();
+    let diagonal_rule = mb.map.diagonal_rule;
     for (entity, map_point, mut fov) in fovs.iter_mut() {
         // illuminate all the tiles within the entities' line of sight
         if fov.is_dirty {
@@ -60,7 +65,7 @@ pub fn field_of_view_system(
                 let idx = mb.map.map_idx(x.x, x.y);
                 let tile = &mut mb.map.tiles[idx];
                 let light_level = tile.illumination_level;
-                let distance = distance_between_points(map_point.0, *x);
+                let distance = distance_between_points(map_point.0, *x, diagonal_rule);
                 let seen_level: SeenLevel = match light_level {
                     IlluminationLevel::None => {
                         if let Some(dark_vision) = fov.dark_vision {
@@ -92,60 +97,163 @@ pub fn field_of_view_system(
             fov.is_dirty = false;
         }
     }
-    let (tile_storage, _) = fog_of_war.single_mut();
+    // Without a fog of war layer, as when running headless, only the map keeps track
+    let fog_of_war = fog_of_war.get_single().ok().map(|(tile_storage, _)| tile_storage);
     for x in changed {
         let tile_pos = mb.map.to_bevy_ecs_tilemap(x.x, x.y);
         let idx = mb.map.map_idx(x.x, x.y);
         let tile = &mut mb.map.tiles[idx];
-        if let Some(tile_entity) = tile_storage.get(&tile_pos) {
-            let mut bright_count = 0;
-            let mut shadowy_count = 0;
-            let mut dark_count = 0;
-            for (_, level) in tile.seen_by.clone() {
-                match level {
-                    SeenLevel::Bright => {
-                        bright_count = bright_count + 1;
-                    },
-                    SeenLevel::Shadowy => {
-                        shadowy_count = shadowy_count + 1;
-                    },
-                    SeenLevel::Darkness => {
-                        dark_count = dark_count + 1;
-                    },
-                    SeenLevel::None => {}
-                }
+        let mut bright_count = 0;
+        let mut shadowy_count = 0;
+        let mut dark_count = 0;
+        // The fog of war only shows what the player's side can see
+        for (viewer, level) in tile.seen_by.clone() {
+            if !players.contains(viewer) {
+                continue;
             }
-            match tile.seen_level {
-                SeenLevel::Bright => {},
+            match level {
+                SeenLevel::Bright => {
+                    bright_count = bright_count + 1;
+                },
                 SeenLevel::Shadowy => {
-                    if bright_count > 0 {
-                        tile.seen_level = SeenLevel::Bright;
-                    }
+                    shadowy_count = shadowy_count + 1;
                 },
                 SeenLevel::Darkness => {
-                    if bright_count > 0 {
-                        tile.seen_level = SeenLevel::Bright;
-                    } else if shadowy_count > 0 {
-                        tile.seen_level = SeenLevel::Shadowy;
-                    }
+                    dark_count = dark_count + 1;
                 },
-                SeenLevel::None => {
-                    if bright_count > 0 {
-                        tile.seen_level = SeenLevel::Bright;
-                    } else if shadowy_count > 0 {
-                        tile.seen_level = SeenLevel::Shadowy;
-                    } else if dark_count > 0 {
-                        tile.seen_level = SeenLevel::Darkness;
-                    }
-                }
+                SeenLevel::None => {}
             }
-            match tile.seen_level {
-                SeenLevel::Bright => { commands.entity(tile_entity).insert(TileColor(Color::rgba(0.0, 0.0, 0.0, 0.0))); },
-                SeenLevel::Shadowy => { commands.entity(tile_entity).insert(TileColor(Color::rgba(0.0, 0.0, 0.0, 0.90))); },
-                SeenLevel::Darkness => { commands.entity(tile_entity).insert(TileColor(Color::rgba(0.0, 0.0, 0.0, 0.99))); },
-                SeenLevel::None => { commands.entity(tile_entity).insert(TileColor(Color::rgba(0.0, 0.0, 0.0, 1.0))); },
+        }
+        match tile.seen_level {
+            SeenLevel::Bright => {},
+            SeenLevel::Shadowy => {
+                if bright_count > 0 {
+                    tile.seen_level = SeenLevel::Bright;
+                }
+            },
+            SeenLevel::Darkness => {
+                if bright_count > 0 {
+                    tile.seen_level = SeenLevel::Bright;
+                } else if shadowy_count > 0 {
+                    tile.seen_level = SeenLevel::Shadowy;
+                }
+            },
+            SeenLevel::None => {
+                if bright_count > 0 {
+                    tile.seen_level = SeenLevel::Bright;
+                } else if shadowy_count > 0 {
+                    tile.seen_level = SeenLevel::Shadowy;
+                } else if dark_count > 0 {
+                    tile.seen_level = SeenLevel::Darkness;
+                }
             }
         }
+        if let Some(tile_entity) = fog_of_war.and_then(|tile_storage| tile_storage.get(&tile_pos)) {
+            commands.entity(tile_entity).insert(TileColor(fog_color(tile.seen_level)));
+        }
+    }
+}
+
+// How much of the map the fog of war hides, by the best look the player's
+// side has had at it
+pub fn fog_color(seen_level: SeenLevel) -> Color {
+    match seen_level {
+        SeenLevel::Bright => Color::rgba(0.0, 0.0, 0.0, 0.0),
+        SeenLevel::Shadowy => Color::rgba(0.0, 0.0, 0.0, 0.90),
+        SeenLevel::Darkness => Color::rgba(0.0, 0.0, 0.0, 0.99),
+        SeenLevel::None => Color::rgba(0.0, 0.0, 0.0, 1.0),
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::systems::illumination::ProvidesIllumination;
+    use crate::systems::test_support::{light, run, viewer, world_of};
+
+    use SeenLevel::{Bright, Darkness, Shadowy};
+
+    // How `viewer` sees each tile, None for tiles out of its sight
+    fn seen_by(world: &World, viewer: Entity) -> Vec<Option<SeenLevel>> {
+        world.resource::<MapBuilder>().map.tiles.iter().map(|tile| tile.seen_by.get(&viewer).copied()).collect()
+    }
+
+    fn seen_levels(world: &World) -> Vec<SeenLevel> {
+        world.resource::<MapBuilder>().map.tiles.iter().map(|tile| tile.seen_level).collect()
+    }
+
+    #[test]
+    fn sight_depends_on_the_light() {
+        let mut world = world_of("________");
+        light(&mut world, 0, 10, 10);
+        let hero = viewer(&mut world, 0, None);
+        world.entity_mut(hero).insert(Player);
+        run(&mut world);
+        let expected = [Bright, Bright, Bright, Shadowy, Shadowy, Darkness, Darkness, Darkness];
+        assert_eq!(seen_by(&world, hero), expected.map(Some));
+        assert_eq!(seen_levels(&world), expected);
+        assert!(!world.get::<FieldOfView>(hero).unwrap().is_dirty);
+    }
+
+    #[test]
+    fn darkvision_sees_dim_light_as_bright_and_darkness_as_dim() {
+        let mut world = world_of("__________");
+        light(&mut world, 0, 5, 10);
+        let dwarf = viewer(&mut world, 0, Some(30));
+        let human = viewer(&mut world, 0, None);
+        run(&mut world);
+        // Normal light to 5 ft and dim light to 15 ft, darkvision to 30 ft
+        assert_eq!(
+            seen_by(&world, dwarf),
+            [Bright, Bright, Bright, Bright, Shadowy, Shadowy, Shadowy, Darkness, Darkness, Darkness].map(Some)
+        );
+        assert_eq!(
+            seen_by(&world, human),
+            [Bright, Bright, Shadowy, Shadowy, Darkness, Darkness, Darkness, Darkness, Darkness, Darkness].map(Some)
+        );
+    }
+
+    #[test]
+    fn sight_darkens_when_a_light_burns_out() {
+        let mut world = world_of("______");
+        let torch = world.spawn((map::MapPoint::new(Point::new(0, 0)), ProvidesIllumination::new(10, 10, Some(30)))).id();
+        let guard = viewer(&mut world, 5, None);
+        run(&mut world);
+        assert_eq!(seen_by(&world, guard)[..3], [Some(Bright); 3]);
+
+        // The guard stays put, so only the light going out can change what it sees
+        world.get_mut::<ProvidesIllumination>(torch).unwrap().burn(30);
+        run(&mut world);
+        assert_eq!(seen_by(&world, guard), [Some(Darkness); 6]);
+    }
+
+    #[test]
+    fn sight_stops_at_tiles_that_block_it() {
+        let mut world = world_of("__#___");
+        light(&mut world, 0, 30, 0);
+        let hero = viewer(&mut world, 0, None);
+        run(&mut world);
+        assert_eq!(seen_by(&world, hero), [Some(Bright), Some(Bright), Some(Bright), None, None, None]);
+    }
+
+    #[test]
+    fn the_map_remembers_the_best_look_a_player_had() {
+        let mut world = world_of("_____#____");
+        light(&mut world, 0, 10, 0);
+        let hero = viewer(&mut world, 0, None);
+        world.entity_mut(hero).insert(Player);
+        let monster = viewer(&mut world, 9, Some(60));
+        run(&mut world);
+        // Only a player's sight reveals the map
+        assert_eq!(seen_by(&world, monster)[6], Some(Shadowy));
+        assert_eq!(seen_levels(&world), [Bright, Bright, Bright, Darkness, Darkness, Darkness, SeenLevel::None, SeenLevel::None, SeenLevel::None, SeenLevel::None]);
+
+        // The hero walks past the curtain and can no longer see the light
+        world.get_mut::<map::MapPoint>(hero).unwrap().0 = Point::new(8, 0);
+        world.get_mut::<FieldOfView>(hero).unwrap().is_dirty = true;
+        run(&mut world);
+        assert_eq!(seen_by(&world, hero)[..5], [None; 5]);
+        assert_eq!(seen_levels(&world), [Bright, Bright, Bright, Darkness, Darkness, Darkness, Darkness, Darkness, Darkness, Darkness]);
+    }
+}
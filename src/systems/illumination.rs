@@ -1,19 +1,35 @@
-use crate::prelude::{*, map_builder::MapBuilder, distance::distance_between_points};
+use crate::prelude::{*, map::{Map, sorted_points}, map_builder::MapBuilder, distance::distance_between_points, field_of_view::FieldOfView, tiles::LightFilter};
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
 pub enum IlluminationLevel {
     None,
     Dim,
     Normal
 }
 
-#[derive(Component, Clone, Debug, PartialEq)]
+impl IlluminationLevel {
+    pub fn dimmer(&self) -> Self {
+        match self {
+            IlluminationLevel::Normal => IlluminationLevel::Dim,
+            _ => IlluminationLevel::None,
+        }
+    }
+}
+
+// Minutes left when a light starts to gutter
+pub const GUTTERING_MINUTES: u32 = 10;
+
+#[derive(Component, Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct ProvidesIllumination {
+    #[serde(with = "sorted_points")]
     pub illuminated_tiles: HashSet<Point>,
     pub bright_interval: u16, // feet from origin
     pub shadowy_interval: u16, // feet, beyond bright
     pub duration: Option<u32>, // in minutes, None means infinite, 0 is burned out
+    #[serde(default)]
+    pub is_magical: bool, // shines into magical darkness
     pub is_dirty: bool
 }
 
@@ -24,33 +40,101 @@ impl ProvidesIllumination {
             bright_interval,
             shadowy_interval,
             duration,
+            is_magical: false,
             is_dirty: true,
         }
     }
+    pub fn is_burned_out(&self) -> bool {
+        self.duration == Some(0)
+    }
+    // A light near its end sheds only dim light, out to its shadowy interval
+    pub fn is_guttering(&self) -> bool {
+        matches!(self.duration, Some(minutes) if minutes > 0 && minutes <= GUTTERING_MINUTES)
+    }
+    // Feet of bright light, and feet the light reaches in all
+    pub fn reach(&self) -> (u16, u16) {
+        if self.is_burned_out() {
+            (0, 0)
+        } else if self.is_guttering() {
+            (0, self.shadowy_interval)
+        } else {
+            (self.bright_interval, self.bright_interval + self.shadowy_interval)
+        }
+    }
+    // Burns the light down, relighting its tiles when that changes its reach
+    pub fn burn(&mut self, minutes: u32) {
+        let Some(duration) = self.duration else {
+            return;
+        };
+        let reach = self.reach();
+        self.duration = Some(duration.saturating_sub(minutes));
+        if self.reach() != reach {
+            self.is_dirty = true;
+        }
+    }
     pub fn clone_dirty(&self) -> Self {
         Self {
             illuminated_tiles: HashSet::new(),
             bright_interval: self.bright_interval,
             shadowy_interval: self.shadowy_interval,
             duration: self.duration,
+            is_magical: self.is_magical,
             is_dirty: true,
         }
     }
 }
 
+// The map as light sees it, stopped by tiles that block light rather than sight
+struct LightMap<'a>(&'a Map);
+
+impl BaseMap for LightMap<'_> {
+    fn is_opaque(&self, idx: usize) -> bool {
+        self.0.tiles[idx].blocks_light
+    }
+}
+
+impl Algorithm2D for LightMap<'_> {
+    fn dimensions(&self) -> Point {
+        self.0.dimensions
+    }
+}
+
+// The light `level` that reaches `to` from a light at `from`, a step dimmer
+// for every tile of fog or smoke on the way and gone inside magical darkness
+// unless the light is magical too
+fn light_reaching(map: &Map, from: Point, to: Point, level: IlluminationLevel, is_magical: bool) -> IlluminationLevel {
+    let filter = |point: &Point| map.tiles[map.map_idx(point.x, point.y)].light_filter;
+    // Mundane light neither reaches into magical darkness nor escapes it
+    if !is_magical && (filter(&from) == LightFilter::MagicalDarkness || filter(&to) == LightFilter::MagicalDarkness) {
+        return IlluminationLevel::None;
+    }
+    line2d_bresenham(from, to)
+        .iter()
+        .skip(1)
+        .filter(|point| filter(point) == LightFilter::Dims)
+        .fold(level, |level, _| level.dimmer())
+}
+
 pub fn illumination_system(
     mut light_sources: Query<(Entity, &map::MapPoint, &mut ProvidesIllumination)>,
+    mut viewers: Query<&mut FieldOfView>,
     mut mb: ResMut<MapBuilder>
 ) {
     let mut changed: HashSet<Point> = HashSet::new();
+    let diagonal_rule = mb.map.diagonal_rule;
     for (entity, map_point, mut provides_illumination) in light_sources.iter_mut() {
         // illuminate all the tiles within the entities' line of sight
         if provides_illumination.is_dirty {
-            let bright_distance = provides_illumination.bright_interval as f32;
-            let shadowy_distance = (provides_illumination.bright_interval + provides_illumination.shadowy_interval) as f32;
-            let range = (provides_illumination.bright_interval + provides_illumination.shadowy_interval) / distance::TILE_SIZE_IN_FEET;
+            let (bright_reach, reach) = provides_illumination.reach();
+            let bright_distance = bright_reach as f32;
+            let shadowy_distance = reach as f32;
+            let range = reach / distance::TILE_SIZE_IN_FEET;
             let old_set = provides_illumination.illuminated_tiles.clone();
-            provides_illumination.illuminated_tiles = field_of_view_set(map_point.0, range as i32, &mb.map);
+            provides_illumination.illuminated_tiles = if provides_illumination.is_burned_out() {
+                HashSet::new()
+            } else {
+                field_of_view_set(map_point.0, range as i32, &LightMap(&mb.map))
+            };
             for x in old_set.difference(&provides_illumination.illuminated_tiles) {
                 let idx = mb.map.map_idx(x.x, x.y);
                 let tile = &mut mb.map.tiles[idx];
@@ -60,14 +144,15 @@ pub fn illumination_system(
             for x in &provides_illumination.illuminated_tiles {
                 // compute the distance fom map_point.0
                 // add or change illumination level
-                let distance = distance_between_points(map_point.0, *x);
-                let light_level = if distance <= bright_distance {
+                let distance = distance_between_points(map_point.0, *x, diagonal_rule);
+                let light_level = if bright_reach > 0 && distance <= bright_distance {
                     IlluminationLevel::Normal
                 } else if distance <= shadowy_distance {
                     IlluminationLevel::Dim
                 } else {
                     IlluminationLevel::None
                 };
+                let light_level = light_reaching(&mb.map, map_point.0, *x, light_level, provides_illumination.is_magical);
                 let idx = mb.map.map_idx(x.x, x.y);
                 let tile = &mut mb.map.tiles[idx];
                 tile.illuminated_by.insert(entity, light_level);
@@ -76,6 +161,13 @@ pub fn illumination_system(
             provides_illumination.is_dirty = false;
         }
     }
+    // Whoever can see a tile whose light changed has to look again, as when
+    // a torch they were seeing by burns out
+    for mut fov in viewers.iter_mut() {
+        if !fov.is_dirty && !fov.visible_tiles.is_disjoint(&changed) {
+            fov.is_dirty = true;
+        }
+    }
     for x in changed {
         // Compute the total illumination of each changed point
         // Change the tile color based on illumination
@@ -103,3 +195,143 @@ pub fn illumination_system(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::systems::test_support::{light, run, world_of};
+
+    use IlluminationLevel::{Dim, None as Dark, Normal};
+
+    fn levels(world: &World) -> Vec<IlluminationLevel> {
+        world.resource::<MapBuilder>().map.tiles.iter().map(|tile| tile.illumination_level).collect()
+    }
+
+    #[test]
+    fn light_fades_from_bright_to_dim_to_dark() {
+        let mut world = world_of("________");
+        let torch = light(&mut world, 0, 10, 10);
+        run(&mut world);
+        assert_eq!(levels(&world), [Normal, Normal, Normal, Dim, Dim, Dark, Dark, Dark]);
+        let tiles = &world.resource::<MapBuilder>().map.tiles;
+        assert_eq!(tiles[3].illuminated_by.get(&torch), Some(&Dim));
+        assert!(tiles[5].illuminated_by.is_empty());
+        assert!(!world.get::<ProvidesIllumination>(torch).unwrap().is_dirty);
+    }
+
+    #[test]
+    fn two_dim_lights_make_normal_light() {
+        let mut world = world_of("_________");
+        light(&mut world, 0, 5, 15);
+        light(&mut world, 8, 5, 15);
+        run(&mut world);
+        // Each lights its own two tiles normally and three more dimly,
+        // and the middle tile is dim from both
+        assert_eq!(levels(&world), [Normal, Normal, Dim, Dim, Normal, Dim, Dim, Normal, Normal]);
+    }
+
+    #[test]
+    fn a_normal_light_outshines_a_dim_one() {
+        let mut world = world_of("_____");
+        light(&mut world, 0, 5, 15);
+        light(&mut world, 4, 20, 0);
+        run(&mut world);
+        assert_eq!(levels(&world), [Normal; 5]);
+    }
+
+    #[test]
+    fn moving_a_light_darkens_the_tiles_it_leaves() {
+        let mut world = world_of("__________");
+        let lantern = light(&mut world, 0, 5, 5);
+        run(&mut world);
+        assert_eq!(levels(&world), [Normal, Normal, Dim, Dark, Dark, Dark, Dark, Dark, Dark, Dark]);
+
+        world.get_mut::<map::MapPoint>(lantern).unwrap().0 = Point::new(9, 0);
+        // Nothing changes until the light is marked dirty
+        run(&mut world);
+        assert_eq!(levels(&world)[0], Normal);
+        world.get_mut::<ProvidesIllumination>(lantern).unwrap().is_dirty = true;
+        run(&mut world);
+        assert_eq!(levels(&world), [Dark, Dark, Dark, Dark, Dark, Dark, Dark, Dim, Normal, Normal]);
+        let mb = world.resource::<MapBuilder>();
+        assert!(mb.map.tiles[0].illuminated_by.is_empty());
+        let lit: HashSet<Point> = (7..10).map(|x| Point::new(x, 0)).collect();
+        assert_eq!(world.get::<ProvidesIllumination>(lantern).unwrap().illuminated_tiles, lit);
+    }
+
+    #[test]
+    fn a_guttering_light_sheds_only_dim_light_then_goes_out() {
+        let mut world = world_of("________");
+        let torch = world
+            .spawn((map::MapPoint::new(Point::new(0, 0)), ProvidesIllumination::new(10, 10, Some(GUTTERING_MINUTES + 1))))
+            .id();
+        run(&mut world);
+        assert_eq!(levels(&world), [Normal, Normal, Normal, Dim, Dim, Dark, Dark, Dark]);
+
+        world.get_mut::<ProvidesIllumination>(torch).unwrap().burn(1);
+        run(&mut world);
+        assert_eq!(levels(&world), [Dim, Dim, Dim, Dark, Dark, Dark, Dark, Dark]);
+
+        world.get_mut::<ProvidesIllumination>(torch).unwrap().burn(GUTTERING_MINUTES);
+        run(&mut world);
+        assert_eq!(levels(&world), [Dark; 8]);
+        assert!(world.resource::<MapBuilder>().map.tiles.iter().all(|tile| tile.illuminated_by.is_empty()));
+        assert!(world.get::<ProvidesIllumination>(torch).unwrap().illuminated_tiles.is_empty());
+    }
+
+    #[test]
+    fn light_stops_at_tiles_that_block_it() {
+        let mut world = world_of("___#___");
+        light(&mut world, 0, 30, 0);
+        run(&mut world);
+        // The curtain itself is lit, like a wall facing the light
+        assert_eq!(levels(&world), [Normal, Normal, Normal, Normal, Dark, Dark, Dark]);
+    }
+
+    #[test]
+    fn stained_glass_lets_light_through() {
+        let mut world = world_of("__|__");
+        light(&mut world, 0, 30, 0);
+        run(&mut world);
+        assert_eq!(levels(&world), [Normal; 5]);
+        let glass = &world.resource::<MapBuilder>().map.tiles[2];
+        assert!(glass.blocks_movement && glass.blocks_sight);
+    }
+
+    #[test]
+    fn fog_dims_light_a_step_per_tile() {
+        let mut world = world_of("_~_~__");
+        light(&mut world, 0, 30, 0);
+        run(&mut world);
+        assert_eq!(levels(&world), [Normal, Dim, Dim, Dark, Dark, Dark]);
+    }
+
+    #[test]
+    fn magical_darkness_only_lets_magical_light_in() {
+        let mut world = world_of("__**__");
+        light(&mut world, 0, 30, 0);
+        run(&mut world);
+        assert_eq!(levels(&world), [Normal, Normal, Dark, Dark, Normal, Normal]);
+
+        let mut world = world_of("__**__");
+        let moonbeam = light(&mut world, 0, 30, 0);
+        world.get_mut::<ProvidesIllumination>(moonbeam).unwrap().is_magical = true;
+        run(&mut world);
+        assert_eq!(levels(&world), [Normal; 6]);
+    }
+
+    #[test]
+    fn mundane_light_in_magical_darkness_lights_nothing() {
+        let mut world = world_of("__*__");
+        light(&mut world, 2, 30, 0);
+        run(&mut world);
+        assert_eq!(levels(&world), [Dark; 5]);
+
+        let mut world = world_of("__*__");
+        let moonbeam = light(&mut world, 2, 30, 0);
+        world.get_mut::<ProvidesIllumination>(moonbeam).unwrap().is_magical = true;
+        run(&mut world);
+        assert_eq!(levels(&world), [Normal; 5]);
+    }
+}
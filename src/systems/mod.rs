@@ -1,17 +1,34 @@
 use crate::prelude::*;
 
+pub mod clock;
+pub mod combat;
+pub mod cover_overlay;
+pub mod encounter;
+pub mod error_screen;
 pub mod illumination;
 pub mod field_of_view;
+pub mod movement;
+pub mod movement_overlay;
+pub mod save;
+pub mod simulation;
+pub mod spawner;
+#[cfg(test)]
+pub mod test_support;
+pub mod turn;
 
 #[derive(Component)]
 pub struct Player;
 
+#[derive(Component)]
+pub struct Monster;
+
+#[allow(clippy::type_complexity)]
 pub fn player_render_system(
-    mut player_transform: Query<&mut Transform, With<Player>>
+    mut creature_transforms: Query<&mut Transform, Or<(With<Player>, With<Monster>)>>
 ) {
     // Temporary system to render the player. A value of 2.4 or lower (intended is 2.0, as the objects were intended to be 1.0.)
     // will not diplay the Sprite. Note that in Bevy 0.9 this wasnt required
-    if let Ok(mut transform) = player_transform.get_single_mut() {
+    for mut transform in creature_transforms.iter_mut() {
         transform.translation.z = 2.5;
     }
 }
\ No newline at end of file
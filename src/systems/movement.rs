@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    *,
+    camera::CursorMapPoint,
+    map::{MapPoint, ObjectsMapLayer},
+    map_builder::MapBuilder,
+    creature::MovementModes,
+    range_finder::RangeFinder,
+    illumination::ProvidesIllumination,
+    field_of_view::FieldOfView,
+    turn::{BeginTurnEvent, CurrentActor, TurnState},
+    movement_overlay::{MovementRange, movement_range_system, movement_overlay_system},
+    encounter::EncounterSet,
+};
+
+use super::Player;
+
+const SECONDS_PER_STEP: f32 = 0.15;
+// Taking the attack action makes one attack
+pub const ATTACKS_PER_TURN: u32 = 1;
+
+#[derive(Component, Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Movement {
+    pub speed: u32, // feet per turn
+    pub remaining: u32, // feet left this turn
+    pub dashed: bool, // dashing adds another speed's worth of feet, once per turn
+    pub modes: MovementModes,
+    #[serde(default = "Movement::attacks_per_turn")]
+    pub attacks: u32, // attacks left this turn
+}
+
+impl Movement {
+    pub fn new(speed: u32, modes: MovementModes) -> Self {
+        Self {
+            speed,
+            remaining: speed,
+            dashed: false,
+            modes,
+            attacks: ATTACKS_PER_TURN,
+        }
+    }
+    fn attacks_per_turn() -> u32 {
+        ATTACKS_PER_TURN
+    }
+    // The furthest this entity could still go this turn if it dashes
+    pub fn dash_range(&self) -> u32 {
+        if self.dashed {
+            self.remaining
+        } else {
+            self.remaining + self.speed
+        }
+    }
+}
+
+// The tiles an entity is still walking through and the feet each step
+// costs, one step per timer tick
+#[derive(Component, Clone, Debug)]
+pub struct MovePath {
+    pub steps: VecDeque<(Point, u32)>,
+    pub timer: Timer,
+}
+
+impl MovePath {
+    pub fn new(steps: VecDeque<(Point, u32)>) -> Self {
+        Self {
+            steps,
+            timer: Timer::from_seconds(SECONDS_PER_STEP, TimerMode::Repeating),
+        }
+    }
+}
+
+pub struct MovementPlugin;
+
+impl Plugin for MovementPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MovementRange>()
+            .add_system(reset_movement_system)
+            .add_system(click_to_move_system.in_set(OnUpdate(TurnState::PlayerTurn)).in_set(EncounterSet))
+            .add_system(follow_path_system.after(click_to_move_system))
+            .add_system(map_point_transform_system.after(follow_path_system).in_set(EncounterSet))
+            .add_system(movement_range_system.after(follow_path_system).after(reset_movement_system).in_set(EncounterSet))
+            .add_system(movement_overlay_system.after(movement_range_system).in_set(EncounterSet));
+    }
+}
+
+pub fn reset_movement_system(
+    mut begin_turn: EventReader<BeginTurnEvent>,
+    mut movers: Query<&mut Movement>,
+) {
+    for event in begin_turn.iter() {
+        if let Ok(mut movement) = movers.get_mut(event.actor) {
+            movement.remaining = movement.speed;
+            movement.dashed = false;
+            movement.attacks = ATTACKS_PER_TURN;
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn click_to_move_system(
+    mut commands: Commands,
+    mouse_input: Res<Input<MouseButton>>,
+    cursor_map_point: Res<CursorMapPoint>,
+    current_actor: Res<CurrentActor>,
+    mut players: Query<(&MapPoint, &mut Movement), (With<Player>, Without<MovePath>)>,
+    mb: Res<MapBuilder>,
+) {
+    if !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let (Some(actor), Some(destination)) = (current_actor.0, cursor_map_point.0) else {
+        return;
+    };
+    let Ok((map_point, mut movement)) = players.get_mut(actor) else {
+        return;
+    };
+    if movement.dash_range() == 0 || destination == map_point.0 {
+        return;
+    }
+    let grid = RangeFinder::compute_grid(map_point.0, movement.dash_range(), &mb.map, movement.modes);
+    if !RangeFinder::get_grid(&grid).contains(&destination) {
+        return;
+    }
+    // Clicking beyond the normal move spends the dash
+    if grid[&destination].path_cost() > movement.remaining {
+        movement.remaining += movement.speed;
+        movement.dashed = true;
+    }
+    // get_path_to walks back from the destination to the anchor, so
+    // reverse it and drop the tile we are already standing on
+    let mut steps: Vec<Point> = RangeFinder::get_path_to(&grid, destination)
+        .into_iter()
+        .rev()
+        .skip(1)
+        .collect();
+    steps.push(destination);
+    let costs = RangeFinder::get_step_costs(&mb.map, map_point.0, &steps, movement.modes);
+    commands.entity(actor).insert(MovePath::new(steps.into_iter().zip(costs).collect()));
+}
+
+#[allow(clippy::type_complexity)]
+pub fn follow_path_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut movers: Query<(
+        Entity,
+        &mut MapPoint,
+        &mut MovePath,
+        &mut Movement,
+        Option<&mut FieldOfView>,
+        Option<&mut ProvidesIllumination>,
+    )>,
+) {
+    for (entity, mut map_point, mut move_path, mut movement, fov, illumination) in movers.iter_mut() {
+        if !move_path.timer.tick(time.delta()).just_finished() {
+            continue;
+        }
+        let Some((next, cost)) = move_path.steps.pop_front() else {
+            commands.entity(entity).remove::<MovePath>();
+            continue;
+        };
+        if cost > movement.remaining {
+            commands.entity(entity).remove::<MovePath>();
+            continue;
+        }
+        movement.remaining -= cost;
+        map_point.0 = next;
+        // Sight and any carried light follow the mover
+        if let Some(mut fov) = fov {
+            fov.is_dirty = true;
+        }
+        if let Some(mut illumination) = illumination {
+            illumination.is_dirty = true;
+        }
+        if move_path.steps.is_empty() {
+            commands.entity(entity).remove::<MovePath>();
+        }
+    }
+}
+
+// Keeps sprites on top of the tile their MapPoint refers to
+#[allow(clippy::type_complexity)]
+pub fn map_point_transform_system(
+    mut moved: Query<(&MapPoint, &mut Transform), Changed<MapPoint>>,
+    layers: Query<(&TilemapGridSize, &Transform), (With<ObjectsMapLayer>, Without<MapPoint>)>,
+    mb: Res<MapBuilder>,
+) {
+    let Ok((grid_size, map_transform)) = layers.get_single() else {
+        return;
+    };
+    for (map_point, mut transform) in moved.iter_mut() {
+        let tile_pos = mb.map.to_bevy_ecs_tilemap(map_point.0.x, map_point.0.y);
+        transform.translation.x = map_transform.translation.x + tile_pos.x as f32 * grid_size.x;
+        transform.translation.y = map_transform.translation.y + tile_pos.y as f32 * grid_size.y;
+    }
+}
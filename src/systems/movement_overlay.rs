@@ -0,0 +1,94 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::prelude::{
+    *,
+    camera::CursorMapPoint,
+    map::{MapPoint, MovementRangeMapLayer},
+    map_builder::MapBuilder,
+    range_finder::{GridPoint, RangeFinder},
+    movement::{MovePath, Movement},
+    turn::CurrentActor,
+};
+
+use super::Player;
+
+const MOVE_COLOR: Color = Color::rgba(0.2, 0.5, 1.0, 0.35);
+const DASH_COLOR: Color = Color::rgba(1.0, 0.8, 0.2, 0.35);
+const PATH_COLOR: Color = Color::rgba(0.3, 1.0, 0.3, 0.6);
+
+// The movement grid of the unit whose turn it is, recomputed only when
+// that unit moves, spends movement or the turn passes on
+#[derive(Resource, Default)]
+pub struct MovementRange {
+    pub selection: Option<(Entity, Point, Movement)>,
+    pub grid: HashMap<Point, GridPoint>,
+}
+
+pub fn movement_range_system(
+    current_actor: Res<CurrentActor>,
+    movers: Query<(&MapPoint, &Movement, Option<&MovePath>), With<Player>>,
+    mb: Res<MapBuilder>,
+    mut range: ResMut<MovementRange>,
+) {
+    // Nothing is selected on someone else's turn, or while walking a path
+    let selection = current_actor.0.and_then(|actor| match movers.get(actor) {
+        Ok((map_point, movement, None)) => Some((actor, map_point.0, *movement)),
+        _ => None,
+    });
+    if selection == range.selection {
+        return;
+    }
+    range.grid = match selection {
+        Some((_, point, movement)) => RangeFinder::compute_grid(point, movement.dash_range(), &mb.map, movement.modes),
+        None => HashMap::new(),
+    };
+    range.selection = selection;
+}
+
+pub fn movement_overlay_system(
+    range: Res<MovementRange>,
+    cursor_map_point: Res<CursorMapPoint>,
+    layers: Query<&TileStorage, With<MovementRangeMapLayer>>,
+    mut tiles: Query<(&mut TileVisible, &mut TileColor)>,
+    mb: Res<MapBuilder>,
+) {
+    if !range.is_changed() && !cursor_map_point.is_changed() {
+        return;
+    }
+    let Ok(tile_storage) = layers.get_single() else {
+        return;
+    };
+    for tile_entity in tile_storage.iter().flatten() {
+        if let Ok((mut visible, _)) = tiles.get_mut(*tile_entity) {
+            visible.0 = false;
+        }
+    }
+    let Some((_, _, movement)) = range.selection else {
+        return;
+    };
+    let reachable = RangeFinder::get_grid(&range.grid);
+    let path: HashSet<Point> = match cursor_map_point.0 {
+        Some(destination) if reachable.contains(&destination) => {
+            let mut path = RangeFinder::get_path_to(&range.grid, destination);
+            path.push(destination);
+            path.into_iter().collect()
+        }
+        _ => HashSet::new(),
+    };
+    for point in reachable {
+        let tile_pos = mb.map.to_bevy_ecs_tilemap(point.x, point.y);
+        let Some(tile_entity) = tile_storage.get(&tile_pos) else {
+            continue;
+        };
+        if let Ok((mut visible, mut color)) = tiles.get_mut(tile_entity) {
+            visible.0 = true;
+            color.0 = if path.contains(&point) {
+                PATH_COLOR
+            } else if range.grid[&point].path_cost() <= movement.remaining {
+                MOVE_COLOR
+            } else {
+                DASH_COLOR
+            };
+        }
+    }
+}
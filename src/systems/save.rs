@@ -0,0 +1,432 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bevy::hierarchy::despawn_with_children_recursive;
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    *,
+    creature::{HitPoints, StatBlock},
+    distance::DiagonalRule,
+    map::{Map, MapPoint, SeenLevel},
+    map_builder::{MapBuilder, custom::{Glyph, Legend}, themes::MapTheme},
+    rng::{GameRng, Seed},
+    tiles::{Tile, TileType},
+    encounter::{EncounterEntity, EncounterLoadedEvent, EncounterSet},
+    clock::{GameClock, TimePassedEvent},
+    error_screen::MapLoadFailed,
+    field_of_view::FieldOfView,
+    illumination::{IlluminationLevel, ProvidesIllumination},
+    movement::Movement,
+    movement_overlay::MovementRange,
+    simulation::Controller,
+    turn::{end_turn_system, BeginTurnEvent, CurrentActor, EncounterMode, Initiative, Round, TurnOrder, TurnState},
+};
+
+use super::{Monster, Player};
+
+// Where F5 saves the encounter being played and F9 loads it back
+pub const QUICKSAVE_PATH: &str = "saves/quicksave.ron";
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Role {
+    Player,
+    Monster,
+    Object, // lights and anything else on the map
+}
+
+// One tile of the map. What it blocks comes from its type, the rest is
+// what the encounter has done to it so far. Entities are by their place
+// in the save's entity list.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SavedTile {
+    pub tile_type: TileType,
+    pub illuminated_by: Vec<(usize, IlluminationLevel)>,
+    pub illumination_level: IlluminationLevel,
+    pub seen_by: Vec<(usize, SeenLevel)>,
+    pub seen_level: SeenLevel,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SavedMap {
+    pub dimensions: Point,
+    pub diagonal_rule: DiagonalRule,
+    pub tiles: Vec<SavedTile>,
+    pub revealed_tiles: Vec<SeenLevel>,
+    pub rooms: Vec<bracket_geometry::prelude::Rect>,
+    pub monster_spawns: Vec<Point>,
+    pub entity_spawns: Vec<(Point, char)>,
+    pub player_start: Point,
+    pub amulet_start: Point,
+    pub theme: MapTheme,
+    pub legend: BTreeMap<char, Glyph>,
+    pub seed: Option<u64>,
+}
+
+// An entity on the map with whichever of its components the rules use
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SavedEntity {
+    pub map_point: Point,
+    pub role: Role,
+    pub sprite_index: Option<usize>,
+    pub stat_block: Option<StatBlock>,
+    pub hit_points: Option<HitPoints>,
+    pub initiative: Option<Initiative>,
+    pub movement: Option<Movement>,
+    pub field_of_view: Option<FieldOfView>,
+    pub illumination: Option<ProvidesIllumination>,
+    pub controller: Option<Controller>,
+}
+
+// An encounter stopped between turns. A creature still walking a path
+// stops on the tile it has reached.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SavedEncounter {
+    pub map: SavedMap,
+    pub entities: Vec<SavedEntity>,
+    pub turn_state: TurnState,
+    pub turn_order: Vec<usize>,
+    pub turn_index: usize,
+    pub round: u32,
+    pub current_actor: Option<usize>,
+    #[serde(default)]
+    pub mode: EncounterMode,
+    #[serde(default)]
+    pub clock: GameClock,
+    // Time passed and a turn begun on the last frame, which the clock and
+    // movement haven't caught up with yet
+    #[serde(default)]
+    pub time_passing: u64,
+    #[serde(default)]
+    pub turn_beginning: Option<usize>,
+    pub rng: RandomNumberGenerator,
+    pub seed: Option<u64>,
+}
+
+// Marks entities spawned from a save, which are drawn once the game
+// gives them their sprite
+#[derive(Component, Copy, Clone, Debug)]
+pub struct Restored {
+    pub sprite_index: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Write(ron::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(error) => write!(f, "{}", error),
+            SaveError::Parse(error) => write!(f, "Couldn't read the save: {}", error),
+            SaveError::Write(error) => write!(f, "Couldn't write the save: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(error: io::Error) -> Self {
+        SaveError::Io(error)
+    }
+}
+
+impl SavedEncounter {
+    // Everything needed to pick the encounter in `world` up again later
+    pub fn capture(world: &mut World) -> Self {
+        let mut query = world.query_filtered::<(
+            Entity,
+            &MapPoint,
+            Option<&StatBlock>,
+            Option<&HitPoints>,
+            Option<&Initiative>,
+            Option<&Movement>,
+            Option<&FieldOfView>,
+            Option<&ProvidesIllumination>,
+            Option<&Controller>,
+            Option<&Player>,
+            Option<&Monster>,
+            Option<&TextureAtlasSprite>,
+        ), With<EncounterEntity>>();
+        let mut ids: HashMap<Entity, usize> = HashMap::new();
+        let mut entities = Vec::new();
+        for (entity, map_point, stat_block, hit_points, initiative, movement, fov, illumination, controller, player, monster, sprite) in query.iter(world) {
+            ids.insert(entity, entities.len());
+            let role = match (player, monster) {
+                (Some(_), _) => Role::Player,
+                (None, Some(_)) => Role::Monster,
+                (None, None) => Role::Object,
+            };
+            entities.push(SavedEntity {
+                map_point: map_point.0,
+                role,
+                sprite_index: sprite.map(|sprite| sprite.index),
+                stat_block: stat_block.cloned(),
+                hit_points: hit_points.copied(),
+                initiative: initiative.copied(),
+                movement: movement.copied(),
+                field_of_view: fov.cloned(),
+                illumination: illumination.cloned(),
+                controller: controller.cloned(),
+            });
+        }
+
+        let turn_order = world.resource::<TurnOrder>();
+        let kept: Vec<usize> = turn_order.order.iter().filter_map(|entity| ids.get(entity).copied()).collect();
+        // Actors that have been despawned are dropped from the order, so the
+        // index moves back past any of them that came before it
+        let turn_index = turn_order.order[..turn_order.index.min(turn_order.order.len())]
+            .iter()
+            .filter(|entity| ids.contains_key(entity))
+            .count();
+        Self {
+            map: SavedMap::from_map_builder(world.resource::<MapBuilder>(), &ids),
+            entities,
+            // A state change asked for on the last frame has yet to happen
+            turn_state: world.resource::<NextState<TurnState>>().0.unwrap_or(world.resource::<State<TurnState>>().0),
+            turn_order: kept,
+            turn_index,
+            round: world.resource::<Round>().0,
+            current_actor: world.resource::<CurrentActor>().0.and_then(|entity| ids.get(&entity).copied()),
+            mode: world.get_resource::<EncounterMode>().copied().unwrap_or_default(),
+            clock: world.get_resource::<GameClock>().copied().unwrap_or_default(),
+            time_passing: world
+                .get_resource::<Events<TimePassedEvent>>()
+                .map_or(0, |events| events.iter_current_update_events().map(|event| event.seconds).sum()),
+            turn_beginning: world
+                .get_resource::<Events<BeginTurnEvent>>()
+                .and_then(|events| events.iter_current_update_events().last())
+                .and_then(|event| ids.get(&event.actor).copied()),
+            rng: world.resource::<GameRng>().0.clone(),
+            seed: world.get_resource::<Seed>().map(|seed| seed.0),
+        }
+    }
+
+    // Spawns the saved entities into `world` and puts the map, turn and
+    // dice back as they were
+    pub fn restore(self, world: &mut World) {
+        let entities: Vec<Entity> = self
+            .entities
+            .into_iter()
+            .map(|saved| {
+                let mut entity = world.spawn((MapPoint::new(saved.map_point), EncounterEntity, Restored { sprite_index: saved.sprite_index }));
+                match saved.role {
+                    Role::Player => { entity.insert(Player); },
+                    Role::Monster => { entity.insert(Monster); },
+                    Role::Object => {},
+                }
+                if let Some(stat_block) = saved.stat_block {
+                    entity.insert(stat_block);
+                }
+                if let Some(hit_points) = saved.hit_points {
+                    entity.insert(hit_points);
+                }
+                if let Some(initiative) = saved.initiative {
+                    entity.insert(initiative);
+                }
+                if let Some(movement) = saved.movement {
+                    entity.insert(movement);
+                }
+                if let Some(fov) = saved.field_of_view {
+                    entity.insert(fov);
+                }
+                if let Some(illumination) = saved.illumination {
+                    entity.insert(illumination);
+                }
+                if let Some(controller) = saved.controller {
+                    entity.insert(controller);
+                }
+                entity.id()
+            })
+            .collect();
+
+        world.insert_resource(self.map.into_map_builder(&entities));
+        world.insert_resource(TurnOrder {
+            order: self.turn_order.iter().map(|id| entities[*id]).collect(),
+            index: self.turn_index,
+        });
+        world.insert_resource(Round(self.round));
+        world.insert_resource(CurrentActor(self.current_actor.map(|id| entities[id])));
+        if let Some(id) = self.turn_beginning {
+            world.send_event(BeginTurnEvent { actor: entities[id], round: self.round });
+        }
+        world.insert_resource(self.mode);
+        world.insert_resource(State(self.turn_state));
+        world.insert_resource(NextState::<TurnState>(None));
+        world.insert_resource(self.clock);
+        if self.time_passing > 0 {
+            world.send_event(TimePassedEvent { seconds: self.time_passing });
+        }
+        world.insert_resource(GameRng(self.rng));
+        if let Some(seed) = self.seed {
+            world.insert_resource(Seed(seed));
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        let path = path.as_ref();
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, ron::to_string(self).map_err(SaveError::Write)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SaveError> {
+        ron::from_str(&fs::read_to_string(path)?).map_err(SaveError::Parse)
+    }
+}
+
+impl SavedMap {
+    fn from_map_builder(mb: &MapBuilder, ids: &HashMap<Entity, usize>) -> Self {
+        // Sorted, so the same encounter always saves the same way
+        fn by_id<T: Copy>(levels: &HashMap<Entity, T>, ids: &HashMap<Entity, usize>) -> Vec<(usize, T)> {
+            let mut saved: Vec<(usize, T)> = levels
+                .iter()
+                .filter_map(|(entity, level)| Some((*ids.get(entity)?, *level)))
+                .collect();
+            saved.sort_by_key(|(id, _)| *id);
+            saved
+        }
+        Self {
+            dimensions: mb.map.dimensions,
+            diagonal_rule: mb.map.diagonal_rule,
+            tiles: mb
+                .map
+                .tiles
+                .iter()
+                .map(|tile| SavedTile {
+                    tile_type: tile.tile_type,
+                    illuminated_by: by_id(&tile.illuminated_by, ids),
+                    illumination_level: tile.illumination_level,
+                    seen_by: by_id(&tile.seen_by, ids),
+                    seen_level: tile.seen_level,
+                })
+                .collect(),
+            revealed_tiles: mb.map.revealed_tiles.clone(),
+            rooms: mb.rooms.clone(),
+            monster_spawns: mb.monster_spawns.clone(),
+            entity_spawns: mb.entity_spawns.clone(),
+            player_start: mb.player_start,
+            amulet_start: mb.amulet_start,
+            theme: mb.theme,
+            legend: mb.legend.0.iter().map(|(c, glyph)| (*c, glyph.clone())).collect(),
+            seed: mb.seed,
+        }
+    }
+
+    fn into_map_builder(self, entities: &[Entity]) -> MapBuilder {
+        let mut map = Map::new(self.dimensions.x as u32, self.dimensions.y as u32, self.theme);
+        map.diagonal_rule = self.diagonal_rule;
+        map.revealed_tiles = self.revealed_tiles;
+        map.tiles = self
+            .tiles
+            .into_iter()
+            .map(|saved| {
+                let mut tile = Tile::new(saved.tile_type);
+                tile.illuminated_by = saved.illuminated_by.into_iter().map(|(id, level)| (entities[id], level)).collect();
+                tile.illumination_level = saved.illumination_level;
+                tile.seen_by = saved.seen_by.into_iter().map(|(id, level)| (entities[id], level)).collect();
+                tile.seen_level = saved.seen_level;
+                tile
+            })
+            .collect();
+        let mut mb = MapBuilder::from_map(map, self.theme);
+        mb.rooms = self.rooms;
+        mb.monster_spawns = self.monster_spawns;
+        mb.entity_spawns = self.entity_spawns;
+        mb.player_start = self.player_start;
+        mb.amulet_start = self.amulet_start;
+        mb.legend = Legend(self.legend.into_iter().collect());
+        mb.seed = self.seed;
+        mb
+    }
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        // Saving after the turn has moved on keeps what it sent for the next
+        // frame in the save
+        app.add_system(quicksave_system.after(end_turn_system).in_set(EncounterSet))
+            .add_system(quickload_system);
+    }
+}
+
+pub fn quicksave_system(world: &mut World) {
+    if !world.resource::<Input<KeyCode>>().just_pressed(KeyCode::F5) {
+        return;
+    }
+    match SavedEncounter::capture(world).save(QUICKSAVE_PATH) {
+        Ok(()) => info!("Saved the encounter to {}", QUICKSAVE_PATH),
+        Err(error) => error!("Couldn't save the encounter to {}: {}", QUICKSAVE_PATH, error),
+    }
+}
+
+// Throws the encounter being played away and puts the quicksave in its place
+pub fn quickload_system(world: &mut World) {
+    if !world.resource::<Input<KeyCode>>().just_pressed(KeyCode::F9) {
+        return;
+    }
+    let saved = match SavedEncounter::load(QUICKSAVE_PATH) {
+        Ok(saved) => saved,
+        Err(error) => {
+            error!("Couldn't load the encounter from {}: {}", QUICKSAVE_PATH, error);
+            return;
+        }
+    };
+    // Tiles aren't children of their tilemap, so they go separately
+    let mut doomed: Vec<Entity> = world
+        .query_filtered::<&TileStorage, With<EncounterEntity>>()
+        .iter(world)
+        .flat_map(|storage| storage.iter().flatten().copied().collect::<Vec<Entity>>())
+        .collect();
+    doomed.extend(world.query_filtered::<Entity, With<EncounterEntity>>().iter(world));
+    for entity in doomed {
+        despawn_with_children_recursive(world, entity);
+    }
+    world.remove_resource::<MapLoadFailed>();
+    world.insert_resource(MovementRange::default());
+    saved.restore(world);
+    world.send_event(EncounterLoadedEvent);
+    info!("Loaded the encounter from {}", QUICKSAVE_PATH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::helpers::map_builder::ArchitectKind;
+    use crate::systems::simulation::{Simulation, SimulationReport};
+
+    fn saved(world: &mut World) -> String {
+        ron::to_string(&SavedEncounter::capture(world)).unwrap()
+    }
+
+    #[test]
+    fn a_loaded_encounter_plays_on_as_if_it_was_never_saved() {
+        let mb = MapBuilder::new_generated(Some(ArchitectKind::Rooms), None, 40, 30, 8);
+        let mut original = Simulation::new(mb, 8, 20).unwrap();
+        for _ in 0..12 {
+            original.app.update();
+        }
+        assert_eq!(original.app.world.resource::<SimulationReport>().outcome, None);
+        let save = saved(&mut original.app.world);
+        let mut loaded = Simulation::restore(ron::from_str(&save).unwrap(), 20);
+        assert_eq!(saved(&mut loaded.app.world), save);
+
+        let played = original.run();
+        let replayed = loaded.run();
+        assert_eq!(replayed.outcome, played.outcome);
+        assert_eq!(replayed.rounds, played.rounds);
+        assert_eq!(saved(&mut loaded.app.world), saved(&mut original.app.world));
+    }
+}
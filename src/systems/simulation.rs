@@ -0,0 +1,518 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    *,
+    cover::{cover_between, Cover},
+    creature::{HitPoints, StatBlock},
+    distance::distance_between_points,
+    map::{Map, MapPoint},
+    map_builder::{custom::MapLoadError, MapBuilder},
+    range_finder::RangeFinder,
+    rng::GameRng,
+    clock::ClockPlugin,
+    combat::{attack_range_advantage, attack_system, slay_system, Advantage, AttackEvent, AttackOutcome, AttackResultEvent},
+    encounter::EncounterEntity,
+    field_of_view::{field_of_view_system, FieldOfView},
+    illumination::{illumination_system, ProvidesIllumination},
+    movement::{reset_movement_system, Movement},
+    save::SavedEncounter,
+    spawner::{creature_bundle, roll_monsters},
+    turn::{end_turn_system, BeginTurnEvent, CurrentActor, EndTurnEvent, Round, TurnPlugin, TurnState},
+};
+
+use super::{Monster, Player};
+
+// One step of a scripted turn. Points name the tile to move to, or the
+// tile of the creature to attack.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum ScriptedAction {
+    MoveTo(Point),
+    Attack(Point),
+    EndTurn,
+}
+
+// Who decides what a creature does with its turn when nobody is at the controls
+#[derive(Component, Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Controller {
+    // Plays each turn up to its EndTurn. Moves that can't be made this turn,
+    // attacks on empty tiles and attacks past the turn's last are skipped,
+    // and once the script runs out the creature passes.
+    Scripted(VecDeque<ScriptedAction>),
+    // Closes on the nearest opponent, preferring ones it can make out, and attacks
+    Ai,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SimulationOutcome {
+    Victory, // every monster is slain
+    Defeat, // every player is down
+    OutOfRounds,
+}
+
+// What one side of the encounter did with its attacks
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SideReport {
+    pub attacks: u32,
+    pub hits: u32,
+    pub damage: i32,
+    pub slain: u32,
+}
+
+#[derive(Resource, Clone, Debug, Default, PartialEq)]
+pub struct SimulationReport {
+    pub outcome: Option<SimulationOutcome>, // None while the encounter goes on
+    pub rounds: u32,
+    pub turns: u32,
+    pub players: SideReport,
+    pub monsters: SideReport,
+    pub player_hit_points: i32, // left between every player
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |report: &SideReport| {
+            format!("hit {} of {} attacks for {} damage, slaying {}", report.hits, report.attacks, report.damage, report.slain)
+        };
+        let outcome = match self.outcome {
+            Some(outcome) => format!("{:?}", outcome),
+            None => "Still going".to_string(),
+        };
+        write!(
+            f,
+            "{} after {} rounds and {} turns. Players {}. Monsters {}. {} player hit points left.",
+            outcome,
+            self.rounds,
+            self.turns,
+            side(&self.players),
+            side(&self.monsters),
+            self.player_hit_points
+        )
+    }
+}
+
+// Rounds a simulated encounter may last before it's called off
+#[derive(Resource, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MaxRounds(pub u32);
+
+// Steps controlled creatures through their turns one turn per update, lighting
+// and sight following every move before its attack is rolled
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationReport>()
+            .add_event::<AttackEvent>()
+            .add_event::<AttackResultEvent>()
+            .add_system(reset_movement_system.before(controller_system))
+            .add_system(controller_system.before(end_turn_system))
+            .add_system(illumination_system.after(controller_system))
+            .add_system(field_of_view_system.after(illumination_system))
+            .add_system(attack_system.after(field_of_view_system))
+            .add_system(slay_system.after(attack_system))
+            .add_system(report_system.after(attack_system).before(end_turn_system))
+            .add_system(outcome_system.after(report_system).after(end_turn_system));
+    }
+}
+
+// A windowless encounter for tests and balance runs
+pub struct Simulation {
+    pub app: App,
+}
+
+impl Simulation {
+    // Spawns the player, monsters and lights of `mb`, every creature played by the AI
+    pub fn new(mb: MapBuilder, seed: u64, max_rounds: u32) -> Result<Self, MapLoadError> {
+        let mut simulation = Self::empty(mb, seed, max_rounds);
+        let app = &mut simulation.app;
+        let mut rng = app.world.remove_resource::<GameRng>().unwrap();
+        let mb = app.world.resource::<MapBuilder>();
+        let player_start = mb.player_start;
+        let hero = StatBlock::load("hero").map_err(MapLoadError::Creature)?;
+        let monsters = roll_monsters(mb, &mut rng.0).map_err(MapLoadError::Creature)?;
+        let lights: Vec<(Point, ProvidesIllumination)> = mb
+            .entity_spawns
+            .iter()
+            .filter_map(|(point, c)| Some((*point, mb.legend.light(*c)?.illumination())))
+            .collect();
+        app.world.insert_resource(rng);
+        app.world.spawn((creature_bundle(hero, player_start), Player, Controller::Ai));
+        for (point, stat_block) in monsters {
+            app.world.spawn((creature_bundle(stat_block, point), Monster, Controller::Ai));
+        }
+        for (point, illumination) in lights {
+            app.world.spawn((illumination, MapPoint::new(point), EncounterEntity));
+        }
+        Ok(simulation)
+    }
+    // Just the map, to spawn creatures and lights into by hand
+    pub fn empty(mb: MapBuilder, seed: u64, max_rounds: u32) -> Self {
+        let mut simulation = Self::headless(max_rounds);
+        simulation.app.insert_resource(GameRng::seeded(seed)).insert_resource(mb);
+        simulation
+    }
+    // Picks a saved encounter up where it was left
+    pub fn restore(saved: SavedEncounter, max_rounds: u32) -> Self {
+        let mut simulation = Self::headless(max_rounds);
+        saved.restore(&mut simulation.app.world);
+        simulation
+    }
+    fn headless(max_rounds: u32) -> Self {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(bevy::input::InputPlugin)
+            .insert_resource(MaxRounds(max_rounds))
+            .add_plugin(TurnPlugin)
+            .add_plugin(ClockPlugin)
+            .add_plugin(SimulationPlugin);
+        Self { app }
+    }
+    // Plays turns until one side is beaten or the rounds run out
+    pub fn run(&mut self) -> SimulationReport {
+        loop {
+            self.app.update();
+            let report = self.app.world.resource::<SimulationReport>();
+            if report.outcome.is_some() {
+                return report.clone();
+            }
+        }
+    }
+}
+
+// A creature in the encounter, as the one taking its turn sees it. Those
+// that are down can't be fought but still take up their tile.
+#[derive(Copy, Clone, Debug)]
+struct Occupant {
+    entity: Entity,
+    point: Point,
+    is_player: bool,
+    standing: bool,
+}
+
+// The first of `stat_block`'s attacks that reaches `target` from `from`
+fn first_attack_in_reach(stat_block: &StatBlock, from: Point, target: Point, map: &Map) -> Option<usize> {
+    if cover_between(map, from, target) == Cover::Full {
+        return None;
+    }
+    stat_block
+        .attacks
+        .iter()
+        .position(|attack| attack_range_advantage(attack, from, target, map.diagonal_rule).is_some())
+}
+
+// The AI's turn: attack the nearest opponent, walking first if it's out of reach
+fn plan_ai_turn(actor: Entity, from: Point, stat_block: &StatBlock, movement: &Movement, is_player: bool, creatures: &[Occupant], map: &Map) -> Vec<ScriptedAction> {
+    let can_make_out = |point: Point| {
+        map.tiles[map.map_idx(point.x, point.y)]
+            .seen_by
+            .get(&actor)
+            .is_some_and(|level| level.can_make_out())
+    };
+    let Some(target) = creatures
+        .iter()
+        .filter(|creature| creature.standing && creature.is_player != is_player)
+        .min_by(|a, b| {
+            let key = |creature: &Occupant| (!can_make_out(creature.point), distance_between_points(from, creature.point, map.diagonal_rule));
+            key(a).partial_cmp(&key(b)).unwrap()
+        })
+    else {
+        return Vec::new();
+    };
+    if first_attack_in_reach(stat_block, from, target.point, map).is_some() {
+        return vec![ScriptedAction::Attack(target.point)];
+    }
+
+    let grid = RangeFinder::compute_grid(from, movement.remaining, map, movement.modes);
+    let free: Vec<(Point, u32)> = grid
+        .iter()
+        .filter(|(point, _)| **point == from || !creatures.iter().any(|creature| creature.point == **point))
+        .map(|(point, grid_point)| (*point, grid_point.path_cost()))
+        .collect();
+    let in_reach = free
+        .iter()
+        .filter(|(point, _)| first_attack_in_reach(stat_block, *point, target.point, map).is_some())
+        .min_by_key(|(point, cost)| (*cost, point.x, point.y));
+    if let Some((point, _)) = in_reach {
+        return vec![ScriptedAction::MoveTo(*point), ScriptedAction::Attack(target.point)];
+    }
+    // Out of reach this turn, so get as close as the walls allow
+    let dijkstra = DijkstraMap::new(map.dimensions.x, map.dimensions.y, &[map.map_idx(target.point.x, target.point.y)], map, 1024.0);
+    let closest = free
+        .iter()
+        .min_by(|a, b| {
+            let key = |(point, cost): &(Point, u32)| (dijkstra.map[map.map_idx(point.x, point.y)], *cost, point.x, point.y);
+            key(a).partial_cmp(&key(b)).unwrap()
+        });
+    match closest {
+        Some((point, _)) if *point != from => vec![ScriptedAction::MoveTo(*point)],
+        _ => Vec::new(),
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn controller_system(
+    state: Res<State<TurnState>>,
+    current_actor: Res<CurrentActor>,
+    mut actors: Query<(
+        Entity,
+        &mut MapPoint,
+        &HitPoints,
+        &StatBlock,
+        &mut Movement,
+        Option<&mut Controller>,
+        Option<&mut FieldOfView>,
+        Option<&mut ProvidesIllumination>,
+        Option<&Player>,
+    )>,
+    mut attacks: EventWriter<AttackEvent>,
+    mut end_turn: EventWriter<EndTurnEvent>,
+    mb: Res<MapBuilder>,
+) {
+    let Some(actor) = current_actor.0 else {
+        return;
+    };
+    if state.0 == TurnState::RollInitiative {
+        return;
+    }
+    let creatures: Vec<Occupant> = actors
+        .iter()
+        .map(|(entity, map_point, hit_points, .., player)| Occupant {
+            entity,
+            point: map_point.0,
+            is_player: player.is_some(),
+            standing: hit_points.current > 0,
+        })
+        .collect();
+    let Ok((_, mut map_point, hit_points, stat_block, mut movement, Some(mut controller), mut fov, mut illumination, player)) = actors.get_mut(actor) else {
+        return;
+    };
+    // Creatures that are down have nothing left to do
+    let actions = if hit_points.current <= 0 {
+        Vec::new()
+    } else {
+        match controller.as_mut() {
+            Controller::Scripted(script) => {
+                let mut turn = Vec::new();
+                while let Some(action) = script.pop_front() {
+                    if action == ScriptedAction::EndTurn {
+                        break;
+                    }
+                    turn.push(action);
+                }
+                turn
+            }
+            Controller::Ai => plan_ai_turn(actor, map_point.0, stat_block, &movement, player.is_some(), &creatures, &mb.map),
+        }
+    };
+    for action in actions {
+        match action {
+            ScriptedAction::MoveTo(destination) => {
+                if creatures.iter().any(|creature| creature.point == destination && creature.entity != actor) {
+                    continue;
+                }
+                let grid = RangeFinder::compute_grid(map_point.0, movement.remaining, &mb.map, movement.modes);
+                let Some(grid_point) = grid.get(&destination) else {
+                    continue;
+                };
+                movement.remaining -= grid_point.path_cost();
+                map_point.0 = destination;
+                // Sight and any carried light follow the mover
+                if let Some(fov) = fov.as_mut() {
+                    fov.is_dirty = true;
+                }
+                if let Some(illumination) = illumination.as_mut() {
+                    illumination.is_dirty = true;
+                }
+            }
+            ScriptedAction::Attack(point) => {
+                let Some(target) = creatures.iter().find(|creature| creature.point == point && creature.standing && creature.entity != actor) else {
+                    continue;
+                };
+                if movement.attacks == 0 {
+                    continue;
+                }
+                if let Some(attack) = first_attack_in_reach(stat_block, map_point.0, point, &mb.map) {
+                    movement.attacks -= 1;
+                    attacks.send(AttackEvent { attacker: actor, target: target.entity, attack, advantage: Advantage::Normal });
+                }
+            }
+            ScriptedAction::EndTurn => {}
+        }
+    }
+    end_turn.send(EndTurnEvent { actor });
+}
+
+pub fn report_system(
+    mut results: EventReader<AttackResultEvent>,
+    mut begin_turn: EventReader<BeginTurnEvent>,
+    players: Query<(), With<Player>>,
+    hit_points: Query<&HitPoints>,
+    mut report: ResMut<SimulationReport>,
+) {
+    report.turns += begin_turn.iter().count() as u32;
+    for result in results.iter() {
+        let side = if players.contains(result.attacker) { &mut report.players } else { &mut report.monsters };
+        side.attacks += 1;
+        if matches!(result.outcome, AttackOutcome::Hit | AttackOutcome::CriticalHit) {
+            side.hits += 1;
+            side.damage += result.damage;
+            if hit_points.get(result.target).is_ok_and(|hit_points| hit_points.current <= 0) {
+                side.slain += 1;
+            }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn outcome_system(
+    creatures: Query<(&HitPoints, Option<&Player>), Or<(With<Player>, With<Monster>)>>,
+    round: Res<Round>,
+    max_rounds: Res<MaxRounds>,
+    mut report: ResMut<SimulationReport>,
+) {
+    if report.outcome.is_some() {
+        return;
+    }
+    let standing = |players: bool| {
+        creatures
+            .iter()
+            .any(|(hit_points, player)| player.is_some() == players && hit_points.current > 0)
+    };
+    report.rounds = round.0.min(max_rounds.0);
+    report.player_hit_points = creatures
+        .iter()
+        .filter(|(_, player)| player.is_some())
+        .map(|(hit_points, _)| hit_points.current.max(0))
+        .sum();
+    report.outcome = if !standing(true) {
+        Some(SimulationOutcome::Defeat)
+    } else if !standing(false) {
+        Some(SimulationOutcome::Victory)
+    } else if round.0 > max_rounds.0 {
+        Some(SimulationOutcome::OutOfRounds)
+    } else {
+        None
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::helpers::map::SeenLevel;
+    use crate::systems::clock::{GameClock, SECONDS_PER_EXPLORATION_TURN, SECONDS_PER_ROUND};
+    use crate::systems::turn::{EncounterMode, TurnOrder};
+    use crate::helpers::map_builder::{ArchitectKind, custom::CustomFileBuilder};
+
+    fn corridor(tiles: &str) -> MapBuilder {
+        let custom_map = ron::from_str(&format!(
+            "(width: {}, height: 1, theme: DungeonTheme, amulet_start: (0, 0), tiles: \"{}\")",
+            tiles.len(),
+            tiles
+        ))
+        .unwrap();
+        CustomFileBuilder::build(&custom_map).unwrap()
+    }
+
+    #[test]
+    fn scripted_turns_move_then_attack() {
+        let mut simulation = Simulation::new(corridor("C@___M_"), 1, 1).unwrap();
+        let world = &mut simulation.app.world;
+        let hero = world.query_filtered::<Entity, With<Player>>().single(world);
+        let monster = world.query_filtered::<Entity, With<Monster>>().single(world);
+        let script = [ScriptedAction::MoveTo(Point::new(4, 0)), ScriptedAction::Attack(Point::new(5, 0)), ScriptedAction::EndTurn];
+        world.entity_mut(hero).insert(Controller::Scripted(script.into()));
+        world.entity_mut(monster).insert(Controller::Scripted(VecDeque::new()));
+
+        let report = simulation.run();
+        let world = &simulation.app.world;
+        assert_eq!(world.get::<MapPoint>(hero).unwrap().0, Point::new(4, 0));
+        assert_eq!(world.get::<Movement>(hero).unwrap().remaining, 15);
+        assert_eq!(report.players.attacks, 1);
+        assert_eq!(report.monsters.attacks, 0);
+        // The hero saw the goblin by the campfire's light from where it stopped
+        let mb = world.resource::<MapBuilder>();
+        assert_eq!(mb.map.tiles[5].seen_by.get(&hero), Some(&SeenLevel::Bright));
+    }
+
+    #[test]
+    fn scripted_creatures_attack_once_a_turn() {
+        let mut simulation = Simulation::new(corridor("C@M"), 1, 1).unwrap();
+        let world = &mut simulation.app.world;
+        let hero = world.query_filtered::<Entity, With<Player>>().single(world);
+        let monster = world.query_filtered::<Entity, With<Monster>>().single(world);
+        let script = [ScriptedAction::Attack(Point::new(1, 0)), ScriptedAction::Attack(Point::new(1, 0)), ScriptedAction::EndTurn];
+        world.entity_mut(hero).insert(Controller::Scripted(VecDeque::new()));
+        world.entity_mut(monster).insert(Controller::Scripted(script.into()));
+
+        let report = simulation.run();
+        assert_eq!(report.monsters.attacks, 1);
+    }
+
+    #[test]
+    fn only_the_players_sight_reveals_the_map() {
+        // Headless runs have no fog of war layer, so the map's seen levels are
+        // all that's left of what the player knows. Monsters see the map too,
+        // but what they see mustn't lift the fog.
+        let mut simulation = Simulation::new(corridor("C@__#__MC"), 1, 1).unwrap();
+        let world = &mut simulation.app.world;
+        let monster = world.query_filtered::<Entity, With<Monster>>().single(world);
+        for creature in world.query_filtered::<Entity, With<Controller>>().iter(world).collect::<Vec<_>>() {
+            world.entity_mut(creature).insert(Controller::Scripted(VecDeque::new()));
+        }
+
+        simulation.run();
+        let mb = simulation.app.world.resource::<MapBuilder>();
+        assert_eq!(mb.map.tiles[6].seen_by.get(&monster), Some(&SeenLevel::Bright));
+        assert_eq!(mb.map.tiles[1].seen_level, SeenLevel::Bright);
+        assert!(mb.map.tiles[5..].iter().all(|tile| tile.seen_level == SeenLevel::None));
+    }
+
+    #[test]
+    fn exploring_passes_a_minute_a_turn_until_a_monster_is_seen() {
+        let mut simulation = Simulation::new(corridor("C@__#__M_"), 1, 20).unwrap();
+        let app = &mut simulation.app;
+        let hero = app.world.query_filtered::<Entity, With<Player>>().single(&app.world);
+        let monster = app.world.query_filtered::<Entity, With<Monster>>().single(&app.world);
+        for creature in [hero, monster] {
+            app.world.entity_mut(creature).insert(Controller::Scripted(VecDeque::new()));
+        }
+
+        // Behind the wall the monster can't be seen, so the fight is over
+        // before it starts and the hero explores alone
+        for _ in 0..4 {
+            app.update();
+        }
+        assert_eq!(*app.world.resource::<EncounterMode>(), EncounterMode::Exploration);
+        assert_eq!(app.world.resource::<TurnOrder>().order, vec![hero]);
+        let before = app.world.resource::<GameClock>().seconds;
+        app.update();
+        assert_eq!(app.world.resource::<GameClock>().seconds - before, SECONDS_PER_EXPLORATION_TURN);
+
+        // Stepping into the firelight starts a fight
+        app.world.get_mut::<MapPoint>(monster).unwrap().0 = Point::new(3, 0);
+        app.update();
+        assert_eq!(*app.world.resource::<EncounterMode>(), EncounterMode::Combat);
+        app.update();
+        assert_eq!(app.world.resource::<TurnOrder>().order.len(), 2);
+        let before = app.world.resource::<GameClock>().seconds;
+        for _ in 0..3 {
+            app.update();
+        }
+        assert_eq!(app.world.resource::<GameClock>().seconds - before, SECONDS_PER_ROUND);
+    }
+
+    #[test]
+    fn the_same_seed_plays_out_the_same() {
+        let play = |seed| {
+            let mb = MapBuilder::new_generated(Some(ArchitectKind::Rooms), None, 40, 30, seed);
+            Simulation::new(mb, seed, 3).unwrap().run()
+        };
+        let first = play(8);
+        assert!(first.outcome.is_some());
+        assert!(first.turns > 0);
+        assert_eq!(first, play(8));
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use crate::prelude::{
+    *,
+    creature::{CreatureError, HitPoints, StatBlock},
+    encounter::EncounterEntity,
+    map::{MapPoint, SeenLevel},
+    map_builder::MapBuilder,
+    rng::pick_weighted,
+    turn::Initiative,
+};
+
+use super::{Monster, Player};
+
+// Everything the rules need to know about a creature standing at `point`
+pub fn creature_bundle(stat_block: StatBlock, point: Point) -> impl Bundle {
+    (
+        stat_block.field_of_view(),
+        MapPoint::new(point),
+        Initiative::new(stat_block.initiative_modifier()),
+        stat_block.movement(),
+        HitPoints::new(stat_block.hit_points),
+        stat_block,
+        EncounterEntity,
+    )
+}
+
+// Rolls which creature of the theme stands at each point in `monster_spawns`
+pub fn roll_monsters(mb: &MapBuilder, rng: &mut RandomNumberGenerator) -> Result<Vec<(Point, StatBlock)>, CreatureError> {
+    let mut stat_blocks: HashMap<&str, StatBlock> = HashMap::new();
+    mb.monster_spawns
+        .iter()
+        .map(|point| {
+            let name = pick_weighted(mb.theme.monster_table(), rng);
+            if !stat_blocks.contains_key(name.as_str()) {
+                stat_blocks.insert(name.as_str(), StatBlock::load(name)?);
+            }
+            Ok((*point, stat_blocks[name.as_str()].clone()))
+        })
+        .collect()
+}
+
+// Spawns the monsters rolled for the map, hidden until the player sees them
+pub fn spawn_monsters(
+    commands: &mut Commands,
+    mb: &MapBuilder,
+    texture_atlas: Handle<TextureAtlas>,
+    mut transform: Transform,
+    monsters: Vec<(Point, StatBlock)>,
+) {
+    let origin = transform.translation;
+    for (point, stat_block) in monsters {
+        let in_b_e_t = mb.map.to_bevy_ecs_tilemap(point.x, point.y);
+        transform.translation.x = origin.x + (in_b_e_t.x * 32) as f32;
+        transform.translation.y = origin.y + (in_b_e_t.y * 32) as f32;
+        commands.spawn((
+            SpriteSheetBundle {
+                sprite: TextureAtlasSprite { index: stat_block.sprite_index, ..Default::default() },
+                texture_atlas: texture_atlas.clone(),
+                transform,
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            creature_bundle(stat_block, point),
+            Monster,
+        ));
+    }
+}
+
+// Monsters are only drawn while a player can currently make them out
+pub fn monster_visibility_system(
+    mut monsters: Query<(&MapPoint, &mut Visibility), With<Monster>>,
+    players: Query<Entity, With<Player>>,
+    mb: Res<MapBuilder>,
+) {
+    for (map_point, mut visibility) in monsters.iter_mut() {
+        let idx = mb.map.map_idx(map_point.0.x, map_point.0.y);
+        let tile = &mb.map.tiles[idx];
+        let seen = players
+            .iter()
+            .any(|player| tile.seen_by.get(&player).is_some_and(SeenLevel::can_make_out));
+        let wanted = if seen { Visibility::Inherited } else { Visibility::Hidden };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+}
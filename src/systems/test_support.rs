@@ -0,0 +1,43 @@
+use crate::prelude::{
+    *,
+    map::{Map, MapPoint},
+    map_builder::{themes::MapTheme, MapBuilder},
+    tiles::Tile,
+    field_of_view::{field_of_view_system, FieldOfView},
+    illumination::{illumination_system, ProvidesIllumination},
+};
+
+// A row of floor tiles, with a curtain anywhere `tiles` has a '#',
+// stained glass for '|', fog for '~' and magical darkness for '*'
+pub fn world_of(tiles: &str) -> World {
+    let mut map = Map::new(tiles.len() as u32, 1, MapTheme::named("DungeonTheme").unwrap());
+    for (x, c) in tiles.chars().enumerate() {
+        match c {
+            '#' => map.tiles[x] = Tile::named("curtain").unwrap(),
+            '|' => map.tiles[x] = Tile::named("stained_glass").unwrap(),
+            '~' => map.tiles[x] = Tile::named("fog").unwrap(),
+            '*' => map.tiles[x] = Tile::named("magical_darkness").unwrap(),
+            _ => {}
+        }
+    }
+    let mut world = World::new();
+    world.insert_resource(MapBuilder::from_map(map, MapTheme::named("DungeonTheme").unwrap()));
+    world
+}
+
+pub fn light(world: &mut World, x: i32, bright_interval: u16, shadowy_interval: u16) -> Entity {
+    world
+        .spawn((MapPoint::new(Point::new(x, 0)), ProvidesIllumination::new(bright_interval, shadowy_interval, None)))
+        .id()
+}
+
+pub fn viewer(world: &mut World, x: i32, dark_vision: Option<u16>) -> Entity {
+    world.spawn((MapPoint::new(Point::new(x, 0)), FieldOfView::new(60, Some(0), dark_vision))).id()
+}
+
+// Lights the map, then looks at it, as a frame of the game would
+pub fn run(world: &mut World) {
+    let mut schedule = Schedule::new();
+    schedule.add_systems((illumination_system, field_of_view_system).chain());
+    schedule.run(world);
+}
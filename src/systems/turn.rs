@@ -0,0 +1,260 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    *,
+    creature::HitPoints,
+    map::{MapPoint, SeenLevel},
+    map_builder::MapBuilder,
+    rng::GameRng,
+};
+
+use super::{
+    Monster, Player,
+    clock::{SECONDS_PER_EXPLORATION_TURN, SECONDS_PER_ROUND, TimePassedEvent},
+    combat::AttackEvent,
+    field_of_view::field_of_view_system,
+    simulation::Controller,
+};
+
+#[derive(States, Copy, Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Hash)]
+pub enum TurnState {
+    #[default]
+    RollInitiative,
+    PlayerTurn,
+    MonsterTurn,
+}
+
+#[derive(Component, Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Initiative {
+    pub modifier: i32,
+    pub roll: Option<i32>, // d20 + modifier, None until initiative is rolled
+}
+
+impl Initiative {
+    pub fn new(modifier: i32) -> Self {
+        Self {
+            modifier,
+            roll: None,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct TurnOrder {
+    pub order: Vec<Entity>,
+    pub index: usize,
+}
+
+// Rounds of combat and exploration turns played so far
+#[derive(Resource, Copy, Clone, Debug, Default)]
+pub struct Round(pub u32);
+
+// While no monster is in sight the players take turns among themselves, a
+// minute at a time. Initiative is rolled as soon as one comes into view.
+#[derive(Resource, Copy, Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub enum EncounterMode {
+    #[default]
+    Combat,
+    Exploration,
+}
+
+#[derive(Resource, Copy, Clone, Debug, Default)]
+pub struct CurrentActor(pub Option<Entity>);
+
+#[derive(Copy, Clone, Debug)]
+pub struct BeginTurnEvent {
+    pub actor: Entity,
+    pub round: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct EndTurnEvent {
+    pub actor: Entity,
+}
+
+pub struct TurnPlugin;
+
+impl Plugin for TurnPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<TurnState>()
+            .init_resource::<GameRng>()
+            .init_resource::<TurnOrder>()
+            .init_resource::<Round>()
+            .init_resource::<CurrentActor>()
+            .init_resource::<EncounterMode>()
+            .add_event::<BeginTurnEvent>()
+            .add_event::<EndTurnEvent>()
+            .add_system(roll_initiative_system.in_set(OnUpdate(TurnState::RollInitiative)))
+            .add_system(player_end_turn_system.in_set(OnUpdate(TurnState::PlayerTurn)))
+            .add_system(monster_turn_system.in_set(OnUpdate(TurnState::MonsterTurn)))
+            .add_system(end_turn_system.after(player_end_turn_system).after(monster_turn_system))
+            .add_system(exploration_system.after(end_turn_system).after(field_of_view_system));
+    }
+}
+
+fn begin_turn(
+    actor: Entity,
+    is_player: bool,
+    round: u32,
+    current_actor: &mut CurrentActor,
+    begin_turn: &mut EventWriter<BeginTurnEvent>,
+    next_state: &mut NextState<TurnState>,
+) {
+    current_actor.0 = Some(actor);
+    begin_turn.send(BeginTurnEvent { actor, round });
+    if is_player {
+        next_state.set(TurnState::PlayerTurn);
+    } else {
+        next_state.set(TurnState::MonsterTurn);
+    }
+}
+
+pub fn roll_initiative_system(
+    mut actors: Query<(Entity, &mut Initiative, Option<&Player>)>,
+    mut rng: ResMut<GameRng>,
+    mut turn_order: ResMut<TurnOrder>,
+    mut round: ResMut<Round>,
+    mut current_actor: ResMut<CurrentActor>,
+    mut begin_turn_events: EventWriter<BeginTurnEvent>,
+    mut next_state: ResMut<NextState<TurnState>>,
+) {
+    if actors.is_empty() {
+        return;
+    }
+    let mut rolls = Vec::new();
+    for (entity, mut initiative, player) in actors.iter_mut() {
+        let roll = rng.0.roll_dice(1, 20) + initiative.modifier;
+        initiative.roll = Some(roll);
+        rolls.push((entity, roll, initiative.modifier, player.is_some()));
+    }
+    // Highest roll acts first. Ties go to the higher modifier, then to the player
+    rolls.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)).then(b.3.cmp(&a.3)));
+    turn_order.order = rolls.iter().map(|(entity, ..)| *entity).collect();
+    turn_order.index = 0;
+    round.0 += 1;
+    let (actor, _, _, is_player) = rolls[0];
+    begin_turn(actor, is_player, round.0, &mut current_actor, &mut begin_turn_events, &mut next_state);
+}
+
+pub fn player_end_turn_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    current_actor: Res<CurrentActor>,
+    mut end_turn: EventWriter<EndTurnEvent>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        if let Some(actor) = current_actor.0 {
+            end_turn.send(EndTurnEvent { actor });
+        }
+    }
+}
+
+pub fn monster_turn_system(
+    current_actor: Res<CurrentActor>,
+    controlled: Query<(), With<Controller>>,
+    mut end_turn: EventWriter<EndTurnEvent>,
+) {
+    // Monsters without a controller have nothing to do, so they pass their
+    // turn straight away
+    if let Some(actor) = current_actor.0 {
+        if !controlled.contains(actor) {
+            end_turn.send(EndTurnEvent { actor });
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn end_turn_system(
+    mut end_turn: EventReader<EndTurnEvent>,
+    actors: Query<Option<&Player>, With<Initiative>>,
+    mut turn_order: ResMut<TurnOrder>,
+    mut round: ResMut<Round>,
+    mode: Res<EncounterMode>,
+    mut current_actor: ResMut<CurrentActor>,
+    mut begin_turn_events: EventWriter<BeginTurnEvent>,
+    mut time_passed: EventWriter<TimePassedEvent>,
+    mut next_state: ResMut<NextState<TurnState>>,
+) {
+    for event in end_turn.iter() {
+        // Ignore stale or out of turn requests
+        if current_actor.0 != Some(event.actor) {
+            continue;
+        }
+        // Walk forward through the order, skipping actors that have since
+        // been despawned, and start a new round when we wrap around
+        let len = turn_order.order.len();
+        let mut next = turn_order.index;
+        let mut found = None;
+        for _ in 0..len {
+            next += 1;
+            if next >= len {
+                next = 0;
+                round.0 += 1;
+                let seconds = match *mode {
+                    EncounterMode::Combat => SECONDS_PER_ROUND,
+                    EncounterMode::Exploration => SECONDS_PER_EXPLORATION_TURN,
+                };
+                time_passed.send(TimePassedEvent { seconds });
+            }
+            if let Ok(player) = actors.get(turn_order.order[next]) {
+                found = Some((turn_order.order[next], player.is_some()));
+                break;
+            }
+        }
+        match found {
+            Some((actor, is_player)) => {
+                turn_order.index = next;
+                begin_turn(actor, is_player, round.0, &mut current_actor, &mut begin_turn_events, &mut next_state);
+            }
+            None => {
+                // Everyone is gone, start over once new actors arrive
+                turn_order.order.clear();
+                turn_order.index = 0;
+                current_actor.0 = None;
+                next_state.set(TurnState::RollInitiative);
+            }
+        }
+    }
+}
+
+// Ends the fight while a player takes their turn with no monster in sight,
+// and starts one again by rolling initiative once a monster is seen or attacked
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn exploration_system(
+    mut attacks: EventReader<AttackEvent>,
+    players: Query<Entity, With<Player>>,
+    monsters: Query<(&MapPoint, &HitPoints), With<Monster>>,
+    mb: Option<Res<MapBuilder>>,
+    mut mode: ResMut<EncounterMode>,
+    mut turn_order: ResMut<TurnOrder>,
+    mut current_actor: ResMut<CurrentActor>,
+    mut next_state: ResMut<NextState<TurnState>>,
+) {
+    let attacked = !attacks.is_empty();
+    attacks.clear();
+    let Some(mb) = mb else {
+        return;
+    };
+    let Some(actor) = current_actor.0 else {
+        return;
+    };
+    let monster_in_sight = monsters.iter().any(|(map_point, hit_points)| {
+        let tile = &mb.map.tiles[mb.map.map_idx(map_point.0.x, map_point.0.y)];
+        hit_points.current > 0 && players.iter().any(|player| tile.seen_by.get(&player).is_some_and(SeenLevel::can_make_out))
+    });
+    match *mode {
+        EncounterMode::Exploration if monster_in_sight || attacked => {
+            *mode = EncounterMode::Combat;
+            turn_order.order.clear();
+            turn_order.index = 0;
+            current_actor.0 = None;
+            next_state.set(TurnState::RollInitiative);
+        }
+        EncounterMode::Combat if !monster_in_sight && !attacked && players.contains(actor) => {
+            // The player whose turn it is carries on, now exploring
+            *mode = EncounterMode::Exploration;
+            turn_order.order.retain(|entity| players.contains(*entity));
+            turn_order.index = turn_order.order.iter().position(|entity| *entity == actor).unwrap_or_default();
+        }
+        _ => {}
+    }
+}